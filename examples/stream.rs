@@ -0,0 +1,54 @@
+use std::io;
+use std::time::Duration;
+
+use dhttp::prelude::*;
+use dhttp::reqres::{res, HttpStream, HttpHeader};
+
+struct ReportService;
+
+impl HttpService for ReportService {
+    async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+        let mut res = res::stream_with(Report { row: 0 });
+        res.content_type = "text/csv".to_string();
+        Ok(res)
+    }
+}
+
+/// Generates a report row by row, without holding it in memory
+struct Report {
+    row: u32,
+}
+
+impl HttpStream for Report {
+    async fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.row == 0 {
+            self.row += 1;
+            return Ok(Some(b"id,square\n".to_vec()));
+        } else if self.row > 10 {
+            // Body ends once this function returns None
+            return Ok(None);
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let row = format!("{},{}\n", self.row, self.row * self.row);
+        self.row += 1;
+        Ok(Some(row.into_bytes()))
+    }
+
+    // Sent after the body to clients with `TE: trailers`
+    fn trailers(&mut self) -> Vec<HttpHeader> {
+        let rows = HttpHeader { name: "X-Rows".to_string(), value: (self.row - 1).to_string() };
+        vec![rows]
+    }
+}
+
+fn main() -> io::Result<()> {
+    dhttp::tokio_rt()?.block_on(http_main())
+}
+
+async fn http_main() -> io::Result<()> {
+    let mut server = HttpServer::new();
+    server.service(ReportService);
+
+    dhttp::serve_tcp("[::]:8080", server).await
+}
@@ -0,0 +1,29 @@
+use std::io;
+
+use dhttp::prelude::*;
+use dhttp::reqres::res;
+use dhttp::tls::{TlsCert, TlsConfig};
+
+struct Hello;
+
+impl HttpService for Hello {
+    async fn request(&self, _route: &str, req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+        let host = req.get_header("Host").unwrap_or("nowhere");
+        Ok(res::text(format!("Hello from {host}, over {:?}\n", req.version)))
+    }
+}
+
+fn main() -> io::Result<()> {
+    dhttp::tokio_rt()?.block_on(http_main())
+}
+
+async fn http_main() -> io::Result<()> {
+    let mut server = HttpServer::new();
+    server.service(Hello);
+
+    // openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -subj /CN=localhost -keyout key.pem -out cert.pem
+    let cert = TlsCert::from_pem_files("cert.pem", "key.pem")?;
+    let tls = TlsConfig::new(cert);
+
+    dhttp::serve_tls("[::]:8443", server, tls).await
+}
@@ -4,9 +4,14 @@ use std::io::{self, ErrorKind};
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::task::{Context, Poll, ready};
+use std::time::Duration;
 
-use tokio::io::{AsyncRead, AsyncBufRead, AsyncWrite, BufReader, ReadBuf, Take};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncBufRead, AsyncWrite, AsyncReadExt, BufReader, ReadBuf};
 use tokio::net::TcpStream;
+use tokio::time::Sleep;
+
+use crate::h1::BodyReader;
 
 /// Async buffered reader stream
 pub trait HttpRead: AsyncBufRead + Unpin + Send + Sync {}
@@ -22,6 +27,12 @@ pub trait HttpConnection: HttpRead + HttpWrite {
     fn getpeername(&self) -> io::Result<SocketAddr>;
     /// Is it HTTPS?
     fn is_secure(&self) -> bool;
+    /// TCP socket that this connection writes to as-is
+    ///
+    /// Allows to send files with `sendfile(2)` on Linux. `None` by default, which falls back to copying
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        None
+    }
 }
 impl HttpConnection for BufReader<TcpStream> {
     fn getpeername(&self) -> io::Result<SocketAddr> {
@@ -31,6 +42,10 @@ impl HttpConnection for BufReader<TcpStream> {
     fn is_secure(&self) -> bool {
         false
     }
+
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        Some(self.get_ref())
+    }
 }
 // rustc why is this not automatic?????
 impl<T: HttpConnection> HttpConnection for &mut T {
@@ -41,10 +56,14 @@ impl<T: HttpConnection> HttpConnection for &mut T {
     fn is_secure(&self) -> bool {
         (**self).is_secure()
     }
+
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        (**self).tcp_stream()
+    }
 }
 
 pub(crate) struct EmitContinue<T: HttpConnection> {
-    pub conn: Take<T>,
+    pub conn: BodyReader<T>,
     pub to_send: &'static [u8],
 }
 
@@ -70,3 +89,155 @@ impl<T: HttpConnection> AsyncBufRead for EmitContinue<T> {
         Pin::new(&mut Pin::into_inner(self).conn).consume(amt)
     }
 }
+
+/// Fails reads or writes that make no progress for too long with [`ErrorKind::TimedOut`]
+pub(crate) struct Timeout<T: HttpConnection> {
+    pub conn: T,
+    /// Changed with [`Timeout::set_read_timeout`]
+    read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    /// Client address from the PROXY protocol header, replaces the peer address
+    pub peer: Option<SocketAddr>,
+    /// Bytes written, for the logger
+    pub written: u64,
+    read_timer: Option<Pin<Box<Sleep>>>,
+    write_timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T: HttpConnection> Timeout<T> {
+    pub(crate) fn new(conn: T) -> Timeout<T> {
+        Timeout { conn, read_timeout: None, write_timeout: None, peer: None, written: 0, read_timer: None, write_timer: None }
+    }
+
+    /// Sets the read timeout for the next operations
+    ///
+    /// Timers of operations that were dropped while pending (like a body read in a `select`) are forgotten,
+    /// otherwise their deadline would carry over to the next request
+    pub(crate) fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+        self.read_timer = None;
+        self.write_timer = None;
+    }
+
+    /// Sends `len` bytes of a file, starting from its current position
+    ///
+    /// Uses `sendfile(2)` on Linux TCP connections, copies through userspace otherwise
+    pub(crate) async fn send_file(&mut self, mut file: File, len: u64) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(tcp) = self.conn.tcp_stream() {
+            crate::util::sendfile::sendfile(tcp, &mut file, len, self.write_timeout).await?;
+            self.written += len;
+            return Ok(());
+        }
+        tokio::io::copy(&mut (&mut file).take(len), self).await?;
+        Ok(())
+    }
+}
+
+/// Polls the timer of a pending operation, creating it if needed
+fn poll_timer(timer: &mut Option<Pin<Box<Sleep>>>, timeout: Option<Duration>, cx: &mut Context<'_>) -> Poll<io::Error> {
+    let Some(timeout) = timeout else { return Poll::Pending };
+    let timer = timer.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+    ready!(timer.as_mut().poll(cx));
+    Poll::Ready(ErrorKind::TimedOut.into())
+}
+
+/// Resets the timer when operation has completed, or checks it otherwise
+fn check_timer<R>(result: Poll<R>, timer: &mut Option<Pin<Box<Sleep>>>, timeout: Option<Duration>, cx: &mut Context<'_>) -> Poll<io::Result<R>> {
+    match result {
+        Poll::Ready(result) => {
+            *timer = None;
+            Poll::Ready(Ok(result))
+        }
+        Poll::Pending => poll_timer(timer, timeout, cx).map(Err),
+    }
+}
+
+impl<T: HttpConnection> AsyncRead for Timeout<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.conn).poll_read(cx, buf);
+        check_timer(result, &mut this.read_timer, this.read_timeout, cx)?
+    }
+}
+
+impl<T: HttpConnection> AsyncBufRead for Timeout<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.conn).poll_fill_buf(cx);
+        check_timer(result, &mut this.read_timer, this.read_timeout, cx)?
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.get_mut().conn).consume(amt)
+    }
+}
+
+impl<T: HttpConnection> AsyncWrite for Timeout<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.conn).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.written += n as u64;
+        }
+        check_timer(result, &mut this.write_timer, this.write_timeout, cx)?
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.conn).poll_flush(cx);
+        check_timer(result, &mut this.write_timer, this.write_timeout, cx)?
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.conn).poll_shutdown(cx);
+        check_timer(result, &mut this.write_timer, this.write_timeout, cx)?
+    }
+}
+
+impl<T: HttpConnection> HttpConnection for Timeout<T> {
+    fn getpeername(&self) -> io::Result<SocketAddr> {
+        match self.peer {
+            Some(peer) => Ok(peer),
+            None => self.conn.getpeername(),
+        }
+    }
+
+    fn is_secure(&self) -> bool {
+        self.conn.is_secure()
+    }
+
+    fn tcp_stream(&self) -> Option<&TcpStream> {
+        self.conn.tcp_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn cancelled_read() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        rt.block_on(async {
+            let (mut client, server) = tokio::io::duplex(64);
+            let mut conn = Timeout::new(BufReader::new(server));
+            conn.set_read_timeout(Some(Duration::from_millis(50)));
+            // read is dropped before its timer fires
+            assert!(tokio::time::timeout(Duration::from_millis(10), conn.fill_buf()).await.is_err());
+            tokio::time::sleep(Duration::from_millis(60)).await;
+
+            // next request gets its own timer
+            conn.set_read_timeout(Some(Duration::from_millis(50)));
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                client.write_all(b"GET").await.unwrap();
+                client
+            });
+            assert_eq!(conn.fill_buf().await.unwrap(), b"GET");
+        });
+    }
+}
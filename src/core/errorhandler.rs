@@ -13,4 +13,11 @@ pub trait HttpErrorHandler: Send + Sync + 'static {
 
     /// Shows a plain error code page for internal errors
     fn plain_code(&self, code: StatusCode) -> HttpResponse;
+
+    /// Shows a plain error code page for a request, like `404 Not found` from a service
+    ///
+    /// Calls [`HttpErrorHandler::plain_code`] by default
+    fn plain_code_for(&self, _req: &HttpRequest, code: StatusCode) -> HttpResponse {
+        self.plain_code(code)
+    }
 }
@@ -0,0 +1,146 @@
+use crate::core::{HttpServiceRaw, HttpService, HttpResult, HttpRead};
+use crate::reqres::HttpRequest;
+
+/// Middleware that wraps a service
+///
+/// A layer gets the request before the inner service (`next`), and decides what to do with it:
+/// - inspect it, or call `next` with a modified copy of the request or another route
+/// - respond on its own, without calling `next`
+/// - change the response or the error that `next` has returned
+///
+/// ```
+/// # use dhttp::prelude::*;
+/// # use dhttp::core::HttpLayer;
+/// struct PoweredBy;
+///
+/// impl HttpLayer for PoweredBy {
+///     async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: &dyn HttpServiceRaw) -> HttpResult {
+///         let mut res = next.request_raw(route, req, body).await?;
+///         res.add_header("X-Powered-By", "DrakoHTTP");
+///         Ok(res)
+///     }
+/// }
+/// ```
+///
+/// Wrap services with [`HttpServiceExt::layer`], or the whole server with [`HttpServer::layer`](crate::server::HttpServer::layer)
+pub trait HttpLayer: Send + Sync + 'static {
+    /// Serve the request, usually by calling `next`
+    ///
+    /// Equivalent signature:
+    /// `async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: &dyn HttpServiceRaw) -> HttpResult`
+    fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: &dyn HttpServiceRaw) -> impl Future<Output = HttpResult> + Send;
+
+    /// Checks if request is valid, before its body is accepted
+    ///
+    /// By default, asks `next`. Layers that respond on their own must accept those requests here too,
+    /// and layers that reject requests should do it here, so the client doesn't send the body for nothing
+    fn filter(&self, route: &str, req: &HttpRequest, next: &dyn HttpServiceRaw) -> HttpResult<()> {
+        next.filter_raw(route, req)
+    }
+}
+
+/// Service wrapped in a layer, see [`HttpServiceExt::layer`]
+pub struct Layered<L: HttpLayer> {
+    pub layer: L,
+    pub inner: Box<dyn HttpServiceRaw>,
+}
+
+impl<L: HttpLayer> Layered<L> {
+    pub fn new(layer: L, inner: impl HttpServiceRaw) -> Layered<L> {
+        Layered { layer, inner: Box::new(inner) }
+    }
+}
+
+impl<L: HttpLayer> HttpService for Layered<L> {
+    async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        self.layer.request(route, req, body, &*self.inner).await
+    }
+
+    fn filter(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        self.layer.filter(route, req, &*self.inner)
+    }
+}
+
+/// Composition helpers for services
+pub trait HttpServiceExt: HttpServiceRaw + Sized {
+    /// Wraps the service in a layer
+    ///
+    /// Layers stack up, the last one sees the request first:
+    /// ```
+    /// # use dhttp::core::{HttpLayer, HttpServiceExt};
+    /// # use dhttp::services::{Router, FilesService};
+    /// # fn f(auth: impl HttpLayer, log: impl HttpLayer) {
+    /// let mut router = Router::new();
+    /// // `log` runs first, then `auth`, then the files
+    /// router.add("/private/", FilesService::new("private").layer(auth).layer(log));
+    /// # }
+    /// ```
+    fn layer<L: HttpLayer>(self, layer: L) -> Layered<L> {
+        Layered::new(layer, self)
+    }
+}
+
+impl<T: HttpServiceRaw> HttpServiceExt for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reqres::{res, HttpVersion, StatusCode};
+    use crate::services::{DefaultService, Router};
+
+    /// Serves `/v1/...` as `/...`, answers `/health` on its own and hides `/secret`
+    struct Gate;
+
+    impl HttpLayer for Gate {
+        async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: &dyn HttpServiceRaw) -> HttpResult {
+            if route == "/health" { return Ok(res::text("ok")); }
+            let route = route.strip_prefix("/v1").unwrap_or(route);
+            next.request_raw(route, req, body).await
+        }
+
+        fn filter(&self, route: &str, req: &HttpRequest, next: &dyn HttpServiceRaw) -> HttpResult<()> {
+            match route {
+                "/health" => Ok(()),
+                "/secret" => Err(StatusCode::FORBIDDEN.into()),
+                _ => next.filter_raw(route.strip_prefix("/v1").unwrap_or(route), req),
+            }
+        }
+    }
+
+    /// Adds a header to responses
+    struct Tag(&'static str);
+
+    impl HttpLayer for Tag {
+        async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: &dyn HttpServiceRaw) -> HttpResult {
+            let mut res = next.request_raw(route, req, body).await?;
+            res.add_header("X-Tag", self.0);
+            Ok(res)
+        }
+    }
+
+    /// Filter, then request, like the server does
+    fn serve(service: &dyn HttpServiceRaw, route: &str) -> HttpResult {
+        let req = HttpRequest { route: route.to_string(), len: Some(0), version: HttpVersion { major: 1, minor: 1 }, ..HttpRequest::default() };
+        service.filter_raw(route, &req)?;
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        rt.block_on(service.request_raw(route, &req, &mut tokio::io::empty()))
+    }
+
+    fn tags(res: &HttpResult) -> Vec<&str> {
+        let res = res.as_ref().unwrap();
+        res.headers.iter().filter(|h| h.name == "X-Tag").map(|h| h.value.as_str()).collect()
+    }
+
+    #[test]
+    fn layers() {
+        let mut router = Router::new();
+        router.add("/api/", DefaultService.layer(Gate).layer(Tag("inner")));
+        let service = router.layer(Tag("outer"));
+
+        // inner tag is added first
+        assert_eq!(tags(&serve(&service, "/api/v1/")), ["inner", "outer"]);
+        assert_eq!(tags(&serve(&service, "/api/health")), ["inner", "outer"]);
+        assert_eq!(serve(&service, "/api/secret").unwrap_err().to_string(), StatusCode::FORBIDDEN.to_string());
+        assert_eq!(serve(&service, "/api/v2").unwrap_err().to_string(), StatusCode::NOT_FOUND.to_string());
+    }
+}
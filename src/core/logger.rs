@@ -1,10 +1,40 @@
+use std::io;
+use std::time::{Duration, SystemTime};
+
 use crate::reqres::{HttpRequest, HttpResponse};
 use crate::core::HttpError;
 
+/// Details of a served request, besides the request and the response
+///
+/// Client's port and the protocol version are in [`HttpRequest`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct HttpLogContext {
+    /// When the service has started handling the request
+    pub start: SystemTime,
+    /// How long it took until the response was sent
+    pub duration: Duration,
+    /// Bytes of the response body that were sent, including upgraded connections
+    pub bytes_sent: u64,
+    /// Number of the request on its connection, from 1. Reused connections have more
+    pub request_number: usize,
+    /// Response was not sent completely, because of a network error or a timeout
+    pub aborted: bool,
+}
+
 /// Logs http requests and errors
+///
+/// Requests are logged after the response is sent, its body is already taken by then
 pub trait HttpLogger: Send + Sync + 'static {
     /// Log a successful request
-    fn log(&self, req: &HttpRequest, res: &HttpResponse);
+    fn log(&self, req: &HttpRequest, res: &HttpResponse, ctx: &HttpLogContext);
     /// Log an error
-    fn err(&self, req: &HttpRequest, res: &HttpResponse, error: &dyn HttpError);
+    fn err(&self, req: &HttpRequest, res: &HttpResponse, error: &dyn HttpError, ctx: &HttpLogContext);
+    /// Log a connection that could not be accepted, like when the file descriptor limit is reached
+    ///
+    /// Listener retries after a short pause, and this is called again only after a successful accept.
+    /// Does nothing by default
+    fn accept_err(&self, error: &io::Error) {
+        let _ = error;
+    }
 }
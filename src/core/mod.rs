@@ -2,10 +2,12 @@
 
 mod service;
 pub use service::{HttpService, HttpServiceRaw};
+mod layer;
+pub use layer::{HttpLayer, HttpServiceExt, Layered};
 mod error;
 pub use error::{HttpError, HttpErrorType};
 mod logger;
-pub use logger::HttpLogger;
+pub use logger::{HttpLogger, HttpLogContext};
 mod errorhandler;
 pub use errorhandler::HttpErrorHandler;
 pub mod connection;
@@ -0,0 +1,224 @@
+//! Client address behind reverse proxies
+//!
+//! Requests from [`HttpServer::trusted_proxies`](crate::server::HttpServer::trusted_proxies)
+//! are traced back through the forwarding header, from the right (the last proxy) to the left.
+//! Every hop is believed only if it was added by a trusted proxy, so clients can't spoof their address
+//! by sending the header themselves: the first untrusted address is the client
+
+use std::net::{IpAddr, Ipv6Addr};
+
+use crate::reqres::HttpRequest;
+use crate::util::cidr::Cidr;
+
+/// Which header the trusted proxies use
+///
+/// Only one is read, because a proxy passes the other one from the client as is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-For`, with `X-Forwarded-Proto` and `X-Forwarded-Host` from the last proxy
+    #[default]
+    XForwardedFor,
+    /// `Forwarded` from RFC 7239, with `for`, `proto` and `host` of every hop
+    Forwarded,
+}
+
+/// One hop, as the proxy that has received it describes it
+#[derive(Debug, Default, PartialEq)]
+struct Hop {
+    /// `None` for `unknown` and obfuscated nodes
+    addr: Option<(IpAddr, u16)>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+/// Parses an address with an optional port, like `192.0.2.1:8080` or `[2001:db8::1]`
+fn parse_node(node: &str) -> Option<(IpAddr, u16)> {
+    // bare IPv6 is only sent in `X-Forwarded-For`
+    if let Ok(ip) = node.parse() { return Some((ip, 0)); }
+    let (ip, port) = match node.strip_prefix('[') {
+        Some(rest) => {
+            let (ip, port) = rest.split_once(']')?;
+            let ip = IpAddr::V6(ip.parse::<Ipv6Addr>().ok()?);
+            if port.is_empty() { return Some((ip, 0)); }
+            (ip, port.strip_prefix(':')?)
+        }
+        None => {
+            let (ip, port) = node.split_once(':')?;
+            (IpAddr::V4(ip.parse().ok()?), port)
+        }
+    };
+    // obfuscated ports like `_abc` are not known
+    if port.starts_with('_') { return Some((ip, 0)); }
+    if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) { return None; }
+    Some((ip, port.parse().ok()?))
+}
+
+/// Splits on `sep` outside of quoted strings
+fn split_quoted(s: &str, sep: u8) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    let mut parts = vec![];
+    for (i, &b) in s.as_bytes().iter().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            _ if b == sep && !quoted => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts.into_iter().map(str::trim).filter(|part| !part.is_empty())
+}
+
+fn unquote(value: &str) -> String {
+    let Some(value) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else { return value.to_string() };
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        out.push(if c == '\\' { chars.next().unwrap_or('\\') } else { c });
+    }
+    out
+}
+
+/// Hops from all `Forwarded` headers, in order
+fn parse_forwarded<'a>(values: impl Iterator<Item = &'a str>) -> Vec<Hop> {
+    let mut hops = vec![];
+    for element in values.flat_map(|value| split_quoted(value, b',')) {
+        let mut hop = Hop::default();
+        for pair in split_quoted(element, b';') {
+            let Some((name, value)) = pair.split_once('=') else { continue };
+            let value = unquote(value.trim());
+            match name.trim().to_ascii_lowercase().as_str() {
+                "for" => hop.addr = parse_node(&value),
+                "proto" => hop.proto = Some(value.to_ascii_lowercase()),
+                "host" => hop.host = Some(value),
+                _ => {}
+            }
+        }
+        hops.push(hop);
+    }
+    hops
+}
+
+/// Hops from `X-Forwarded-For`. Last hop gets the rightmost `X-Forwarded-Proto` and `X-Forwarded-Host`
+fn parse_x_forwarded(req: &HttpRequest) -> Vec<Hop> {
+    let list = |name| req.get_headers(name).flat_map(|v| v.split(',')).map(str::trim).filter(|v| !v.is_empty());
+    let mut hops: Vec<Hop> = list("X-Forwarded-For").map(|addr| Hop { addr: parse_node(addr), ..Hop::default() }).collect();
+    if let Some(last) = hops.last_mut() {
+        last.proto = list("X-Forwarded-Proto").last().map(str::to_ascii_lowercase);
+        last.host = list("X-Forwarded-Host").last().map(String::from);
+    }
+    hops
+}
+
+/// `host[:port]`, nothing that could change the meaning of a URL
+fn is_host(host: &str) -> bool {
+    !host.is_empty() && host.bytes().all(|b| b.is_ascii_graphic() && !b"/?#@\\\"".contains(&b))
+}
+
+/// Replaces the address, scheme and host of a request with the ones from trusted proxies
+pub(crate) fn resolve(req: &mut HttpRequest, trusted: &[Cidr], header: ForwardedHeader) {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(req.addr) { return; }
+
+    let hops = match header {
+        ForwardedHeader::XForwardedFor => parse_x_forwarded(req),
+        ForwardedHeader::Forwarded => parse_forwarded(req.get_headers("Forwarded")),
+    };
+    for hop in hops.into_iter().rev() {
+        // this hop was added by the client or an untrusted proxy
+        if !is_trusted(req.addr) { break; }
+        // trusted proxy doesn't know the address, so we don't either
+        let Some((addr, port)) = hop.addr else { break };
+        req.addr = addr.to_canonical();
+        req.port = port;
+        if let Some(proto) = hop.proto.filter(|p| p == "http" || p == "https") {
+            req.scheme = proto;
+        }
+        if let Some(host) = hop.host.filter(|h| is_host(h)) {
+            req.host = Some(host);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reqres::HttpHeader;
+
+    fn request(addr: &str, headers: &[(&str, &str)]) -> HttpRequest {
+        HttpRequest {
+            addr: addr.parse().unwrap(),
+            port: 40000,
+            host: Some("internal".to_string()),
+            headers: headers.iter().map(|(n, v)| HttpHeader { name: n.to_string(), value: v.to_string() }).collect(),
+            ..HttpRequest::default()
+        }
+    }
+
+    fn resolved(req: &mut HttpRequest, header: ForwardedHeader) -> (String, u16, &str, &str) {
+        let trusted = ["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()];
+        resolve(req, &trusted, header);
+        (req.addr.to_string(), req.port, req.scheme.as_str(), req.host.as_deref().unwrap())
+    }
+
+    #[test]
+    fn nodes() {
+        assert_eq!(parse_node("192.0.2.1"), Some(("192.0.2.1".parse().unwrap(), 0)));
+        assert_eq!(parse_node("192.0.2.1:8080"), Some(("192.0.2.1".parse().unwrap(), 8080)));
+        assert_eq!(parse_node("[2001:db8::1]:_hidden"), Some(("2001:db8::1".parse().unwrap(), 0)));
+        assert_eq!(parse_node("[2001:db8::1]"), Some(("2001:db8::1".parse().unwrap(), 0)));
+        assert_eq!(parse_node("2001:db8::1"), Some(("2001:db8::1".parse().unwrap(), 0)));
+        for bad in ["unknown", "_hidden", "192.0.2.1:", "192.0.2.1:+80", "[192.0.2.1]", "[2001:db8::1]80", "example.com"] {
+            assert_eq!(parse_node(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn x_forwarded() {
+        let headers = [
+            ("X-Forwarded-For", "203.0.113.9, 198.51.100.7"),
+            ("X-Forwarded-For", "10.1.1.1"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "example.com"),
+        ];
+        // 10.1.1.1 is our proxy, and 198.51.100.7 is the first untrusted one
+        let mut req = request("10.0.0.1", &headers);
+        assert_eq!(resolved(&mut req, ForwardedHeader::XForwardedFor), ("198.51.100.7".to_string(), 0, "https", "example.com"));
+
+        // headers from untrusted clients are ignored
+        let mut req = request("198.51.100.7", &headers);
+        assert_eq!(resolved(&mut req, ForwardedHeader::XForwardedFor), ("198.51.100.7".to_string(), 40000, "http", "internal"));
+
+        // only the chosen header is read
+        let mut req = request("10.0.0.1", &[("Forwarded", "for=192.0.2.1")]);
+        assert_eq!(resolved(&mut req, ForwardedHeader::XForwardedFor).0, "10.0.0.1");
+
+        // invalid values end the chain
+        let mut req = request("10.0.0.1", &[("X-Forwarded-For", "192.0.2.1, garbage, 10.1.1.1"), ("X-Forwarded-Proto", "gopher")]);
+        assert_eq!(resolved(&mut req, ForwardedHeader::XForwardedFor), ("10.1.1.1".to_string(), 0, "http", "internal"));
+    }
+
+    #[test]
+    fn forwarded() {
+        let headers = [
+            ("Forwarded", "for=192.0.2.1;proto=http, for=\"[2001:db8::17]:4711\";proto=https;host=\"example.com\""),
+            ("forwarded", "for=\"[fd00::1]\";by=10.0.0.1;proto=http;host=a.internal"),
+        ];
+        let mut req = request("10.0.0.1", &headers);
+        assert_eq!(resolved(&mut req, ForwardedHeader::Forwarded), ("2001:db8::17".to_string(), 4711, "https", "example.com"));
+
+        // IPv4-mapped addresses are canonical, obfuscated ones end the chain
+        let mut req = request("10.0.0.1", &[("Forwarded", "for=_hidden, for=\"[::ffff:10.2.2.2]\"")]);
+        assert_eq!(resolved(&mut req, ForwardedHeader::Forwarded).0, "10.2.2.2");
+
+        // quoted separators, and hosts that could break URLs
+        let mut req = request("10.0.0.1", &[("Forwarded", "for=192.0.2.1;host=\"a/b;c,d\"")]);
+        assert_eq!(resolved(&mut req, ForwardedHeader::Forwarded), ("192.0.2.1".to_string(), 0, "http", "internal"));
+    }
+}
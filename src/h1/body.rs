@@ -0,0 +1,69 @@
+//! Request body reader
+
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncBufRead, AsyncReadExt, ReadBuf, Take};
+
+use crate::h1::chunked::ChunkedReader;
+use crate::reqres::HttpHeader;
+
+/// Reads the request body, framed either by `Content-Length` or by chunked encoding
+pub(crate) enum BodyReader<T> {
+    Length(Take<T>),
+    Chunked(ChunkedReader<T>),
+}
+
+impl<T: AsyncBufRead + Unpin> BodyReader<T> {
+    /// Creates a reader for a body of `len` bytes, or a chunked body if `len` is unknown
+    ///
+    /// Trailers of a chunked body are put into `trailers`, `strict` validates them like headers
+    pub(crate) fn new(conn: T, len: Option<u64>, trailers: Arc<OnceLock<Vec<HttpHeader>>>, strict: bool) -> BodyReader<T> {
+        match len {
+            Some(len) => BodyReader::Length(conn.take(len)),
+            None => BodyReader::Chunked(ChunkedReader::new(conn, trailers, strict)),
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        match self {
+            BodyReader::Length(take) => take.get_mut(),
+            BodyReader::Chunked(chunked) => chunked.get_mut(),
+        }
+    }
+
+    /// Was the body consumed completely? If not, connection can't be reused
+    pub(crate) fn is_finished(&self) -> bool {
+        match self {
+            BodyReader::Length(take) => take.limit() == 0,
+            BodyReader::Chunked(chunked) => chunked.is_finished(),
+        }
+    }
+}
+
+impl<T: AsyncBufRead + Unpin> AsyncRead for BodyReader<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            BodyReader::Length(take) => Pin::new(take).poll_read(cx, buf),
+            BodyReader::Chunked(chunked) => Pin::new(chunked).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<T: AsyncBufRead + Unpin> AsyncBufRead for BodyReader<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        match self.get_mut() {
+            BodyReader::Length(take) => Pin::new(take).poll_fill_buf(cx),
+            BodyReader::Chunked(chunked) => Pin::new(chunked).poll_fill_buf(cx),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        match self.get_mut() {
+            BodyReader::Length(take) => Pin::new(take).consume(amt),
+            BodyReader::Chunked(chunked) => Pin::new(chunked).consume(amt),
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! `Transfer-Encoding: chunked` request body decoder
+
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, AsyncBufRead, ReadBuf};
+
+use crate::h1::{parse_header, parse_header_strict};
+use crate::reqres::HttpHeader;
+
+/// Chunk size lines and trailer lines longer than this are rejected
+const MAX_LINE: usize = 4096;
+/// Limit of the whole trailer section
+const MAX_TRAILERS: usize = 16384;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Reading `size[;ext]\r\n`
+    Size,
+    /// Reading chunk data, with this many bytes left
+    Data(u64),
+    /// Reading `\r\n` after chunk data
+    DataEnd,
+    /// Reading trailer fields until an empty line
+    Trailers,
+    /// Body has ended
+    Done,
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Strips `\r\n` or `\n` from the end of line
+fn strip_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Parses the chunk size line, ignoring chunk extensions
+fn parse_size(line: &[u8]) -> Option<u64> {
+    let line = strip_newline(line);
+    // chunk-ext starts with ';', possibly after whitespace
+    let size = match line.iter().position(|&c| c == b';') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let size = size.trim_ascii_end();
+    // from_str_radix would accept a leading `+`
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) { return None; }
+    u64::from_str_radix(str::from_utf8(size).ok()?, 16).ok()
+}
+
+/// Decodes a chunked body from the underlying stream
+///
+/// Chunk extensions are ignored. Trailer fields are stored into `slot` once the body ends.
+/// In strict mode, trailer lines are validated like request headers
+pub(crate) struct ChunkedReader<T> {
+    conn: T,
+    state: State,
+    /// Line being read (size line or trailer line)
+    line: Vec<u8>,
+    /// Trailers read so far
+    trailers: Vec<HttpHeader>,
+    /// Amount of trailer bytes read so far
+    trailers_len: usize,
+    /// Where to put trailers (`HttpRequest::trailers`)
+    slot: Arc<OnceLock<Vec<HttpHeader>>>,
+    /// `HttpServer::strict`
+    strict: bool,
+}
+
+impl<T: AsyncBufRead + Unpin> ChunkedReader<T> {
+    pub(crate) fn new(conn: T, slot: Arc<OnceLock<Vec<HttpHeader>>>, strict: bool) -> ChunkedReader<T> {
+        ChunkedReader { conn, state: State::Size, line: vec![], trailers: vec![], trailers_len: 0, slot, strict }
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        &mut self.conn
+    }
+
+    /// Was the whole body including trailers read?
+    pub(crate) fn is_finished(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Reads a line into `self.line`
+    fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let buf = ready!(Pin::new(&mut self.conn).poll_fill_buf(cx))?;
+            if buf.is_empty() { return Poll::Ready(Err(ErrorKind::UnexpectedEof.into())); }
+
+            let (amt, done) = match buf.iter().position(|&c| c == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (buf.len(), false),
+            };
+            self.line.extend_from_slice(&buf[..amt]);
+            Pin::new(&mut self.conn).consume(amt);
+
+            if self.line.len() > MAX_LINE { return Poll::Ready(Err(invalid("chunked line too long"))); }
+            if done { return Poll::Ready(Ok(())); }
+        }
+    }
+
+    /// Advances the state machine until there is data to read or the body has ended
+    fn poll_advance(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match self.state {
+                State::Data(_) | State::Done => return Poll::Ready(Ok(())),
+                State::Size => {
+                    ready!(self.poll_line(cx))?;
+                    let size = parse_size(&self.line).ok_or_else(|| invalid("invalid chunk size"))?;
+                    self.line.clear();
+                    self.state = if size == 0 { State::Trailers } else { State::Data(size) };
+                }
+                State::DataEnd => {
+                    ready!(self.poll_line(cx))?;
+                    if !strip_newline(&self.line).is_empty() { return Poll::Ready(Err(invalid("chunk data too long"))); }
+                    self.line.clear();
+                    self.state = State::Size;
+                }
+                State::Trailers => {
+                    ready!(self.poll_line(cx))?;
+                    self.trailers_len += self.line.len();
+                    if self.trailers_len > MAX_TRAILERS { return Poll::Ready(Err(invalid("trailers too long"))); }
+
+                    let line = strip_newline(&self.line);
+                    // same line endings as in the headers
+                    if self.strict && (!self.line.ends_with(b"\r\n") || line.contains(&b'\r')) {
+                        return Poll::Ready(Err(invalid("invalid trailer")));
+                    }
+                    if line.is_empty() {
+                        self.state = State::Done;
+                        let _ = self.slot.set(std::mem::take(&mut self.trailers));
+                    } else {
+                        let strict = self.strict;
+                        let trailer = str::from_utf8(line).ok().and_then(|line| {
+                            if strict { parse_header_strict(line).ok() } else { parse_header(line) }
+                        });
+                        let trailer = trailer.ok_or_else(|| invalid("invalid trailer"))?;
+                        self.trailers.push(trailer);
+                    }
+                    self.line.clear();
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsyncBufRead + Unpin> AsyncRead for ChunkedReader<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let data = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let amt = data.len().min(buf.remaining());
+        buf.put_slice(&data[..amt]);
+        self.consume(amt);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncBufRead + Unpin> AsyncBufRead for ChunkedReader<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        ready!(this.poll_advance(cx))?;
+
+        let State::Data(left) = this.state else { return Poll::Ready(Ok(&[])) };
+        let buf = ready!(Pin::new(&mut this.conn).poll_fill_buf(cx))?;
+        if buf.is_empty() { return Poll::Ready(Err(ErrorKind::UnexpectedEof.into())); }
+        let amt = (buf.len() as u64).min(left) as usize;
+        Poll::Ready(Ok(&buf[..amt]))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        if amt == 0 { return; }
+        let State::Data(left) = self.state else { return };
+        let left = left - amt as u64;
+        self.state = if left == 0 { State::DataEnd } else { State::Data(left) };
+        Pin::new(&mut self.conn).consume(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncBufReadExt};
+
+    use super::*;
+
+    async fn decode(input: &[u8]) -> (io::Result<Vec<u8>>, usize) {
+        let (result, left, _) = decode_trailers(input, false).await;
+        (result, left)
+    }
+
+    async fn decode_trailers(mut input: &[u8], strict: bool) -> (io::Result<Vec<u8>>, usize, Option<Vec<HttpHeader>>) {
+        let slot = Arc::new(OnceLock::new());
+        let mut reader = ChunkedReader::new(&mut input, Arc::clone(&slot), strict);
+        let mut out = vec![];
+        let result = reader.read_to_end(&mut out).await.map(|_| out);
+        (result, input.len(), slot.get().cloned())
+    }
+
+    fn run<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(f)
+    }
+
+    #[test]
+    fn simple() {
+        let (out, left) = run(decode(b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\nGET / HTTP/1.1"));
+        assert_eq!(out.unwrap(), b"hello world");
+        // next pipelined request must stay untouched
+        assert_eq!(left, 14);
+    }
+
+    #[test]
+    fn extensions_and_trailers() {
+        let input = b"a;name=value\r\n0123456789\r\nA ; quoted=\"a;b\"\r\n0123456789\r\n0\r\nChecksum: abc\r\nX-Other: 1\r\n\r\n";
+        let (out, left, trailers) = run(decode_trailers(input, true));
+        assert_eq!(out.unwrap(), b"01234567890123456789");
+        assert_eq!(left, 0);
+        let trailers = trailers.unwrap();
+        assert_eq!(trailers.len(), 2);
+        assert_eq!((trailers[0].name.as_str(), trailers[0].value.as_str()), ("Checksum", "abc"));
+        assert_eq!((trailers[1].name.as_str(), trailers[1].value.as_str()), ("X-Other", "1"));
+    }
+
+    #[test]
+    fn bare_lf() {
+        let (out, _) = run(decode(b"3\nabc\n0\n\n"));
+        assert_eq!(out.unwrap(), b"abc");
+    }
+
+    #[test]
+    fn invalid() {
+        for input in [
+            &b"+5\r\nhello\r\n0\r\n\r\n"[..],
+            b"\r\nhello\r\n0\r\n\r\n",
+            b"zz\r\nhello\r\n0\r\n\r\n",
+            b"3\r\nhello\r\n0\r\n\r\n",
+            b"5\r\nhello\r\n0\r\nno colon\r\n\r\n",
+            b"ffffffffffffffffff\r\n",
+        ] {
+            let (out, _) = run(decode(input));
+            assert_eq!(out.unwrap_err().kind(), ErrorKind::InvalidData, "{}", input.escape_ascii());
+        }
+    }
+
+    #[test]
+    fn strict_trailers() {
+        for input in [
+            &b"0\r\nX(a: 1\r\n\r\n"[..],
+            b"0\r\n X: 1\r\n\r\n",
+            b"0\r\nX: a\x01b\r\n\r\n",
+            b"0\r\nX: 1\n\r\n",
+            b"0\r\nX: 1\r\n\n",
+        ] {
+            let (out, _, _) = run(decode_trailers(input, false));
+            assert!(out.is_ok(), "{}", input.escape_ascii());
+            let (out, _, _) = run(decode_trailers(input, true));
+            assert_eq!(out.unwrap_err().kind(), ErrorKind::InvalidData, "{}", input.escape_ascii());
+        }
+    }
+
+    #[test]
+    fn early_eof() {
+        let (out, _) = run(decode(b"5\r\nhel"));
+        assert_eq!(out.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        let (out, _) = run(decode(b"5\r\nhello\r\n0\r\n"));
+        assert_eq!(out.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn buf_read() {
+        run(async {
+            let mut input = &b"4\r\nab\nc\r\n2\r\nd\n\r\n0\r\n\r\n"[..];
+            let mut reader = ChunkedReader::new(&mut input, Arc::new(OnceLock::new()), false);
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "ab\n");
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "cd\n");
+            assert!(!reader.is_finished());
+            line.clear();
+            assert_eq!(reader.read_line(&mut line).await.unwrap(), 0);
+            assert!(reader.is_finished());
+        });
+    }
+}
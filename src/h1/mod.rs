@@ -0,0 +1,484 @@
+//! HTTP/1.1 request parsing/reading
+
+use std::io::{self, ErrorKind};
+use std::fmt::{self, Write};
+use std::string::FromUtf8Error;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, OnceLock};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+use crate::reqres::{HttpRequest, HttpResponse, HttpHeader, HttpVersion, HttpMethod, HttpBody, StatusCode, RequestTarget};
+use crate::reqres::body::{BytesStream, ReaderStream};
+use crate::core::connection::{HttpRead, HttpConnection, Timeout};
+use crate::server::HttpServer;
+
+mod chunked;
+mod body;
+pub(crate) use body::BodyReader;
+#[cfg(test)]
+mod tests;
+
+fn parse_ver(ver: &str) -> Option<HttpVersion> {
+    let mut split = ver.strip_prefix("HTTP/")?.split('.');
+    let major = split.next()?.parse().ok()?;
+    let minor = split.next()?.parse().ok()?;
+    if split.next().is_some() {
+        // 3rd element is invalid
+        return None;
+    }
+    Some(HttpVersion { major, minor })
+}
+
+/// `HTTP/` DIGIT `.` DIGIT
+fn parse_ver_strict(ver: &str) -> Option<HttpVersion> {
+    let &[major, b'.', minor] = ver.strip_prefix("HTTP/")?.as_bytes() else { return None };
+    if !major.is_ascii_digit() || !minor.is_ascii_digit() { return None; }
+    Some(HttpVersion { major: major - b'0', minor: minor - b'0' })
+}
+
+fn parse_header(header: &str) -> Option<HttpHeader> {
+    let colon = header.find(':')?;
+    let name = header[..colon].to_string();
+    // whitespace between name and colon made the header invisible to get_header
+    // while other servers could still accept it, so it's rejected even when not strict
+    if name.ends_with([' ', '\t']) { return None; }
+    let value = header[colon+1..].trim().to_string();
+    Some(HttpHeader { name, value })
+}
+
+fn parse_header_strict(header: &str) -> Result<HttpHeader, HttpRequestError> {
+    if header.starts_with([' ', '\t']) { return Err(HttpRequestError::ObsFold); }
+    let (name, value) = header.split_once(':').ok_or(HttpRequestError::InvalidHeader)?;
+    if !is_token(name) { return Err(HttpRequestError::InvalidHeader); }
+    let value = value.trim_matches([' ', '\t']);
+    if value.contains(|c: char| c.is_ascii_control() && c != '\t') { return Err(HttpRequestError::InvalidHeader); }
+    Ok(HttpHeader { name: name.to_string(), value: value.to_string() })
+}
+
+fn split3(line: &str) -> Option<(&str, &str, &str)> {
+    let mut split = line.split_whitespace();
+    let (method, route, version) = (split.next()?, split.next()?, split.next()?);
+    if split.next().is_some() {
+        // 4th element is invalid
+        return None;
+    }
+    Some((method, route, version))
+}
+
+/// Exactly one space between components, method is a token, route is visible ASCII
+fn split3_strict(line: &str) -> Result<(&str, &str, &str), HttpRequestError> {
+    let mut split = line.split(' ');
+    let (Some(method), Some(route), Some(version), None) = (split.next(), split.next(), split.next(), split.next()) else {
+        return Err(HttpRequestError::InvalidPrelude);
+    };
+    if !is_token(method) { return Err(HttpRequestError::InvalidMethod); }
+    if route.is_empty() || !route.bytes().all(|c| c.is_ascii_graphic()) { return Err(HttpRequestError::InvalidPrelude); }
+    Ok((method, route, version))
+}
+
+/// tchar from RFC 9110
+fn is_tchar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(is_tchar)
+}
+
+/// Reads a line of at most `limit` bytes, without the line ending
+///
+/// Strict mode only accepts `\r\n` line endings and rejects any other `\r`.
+/// Bytes read from the connection, with the line ending, are added to `size`
+async fn read_line(conn: &mut impl HttpRead, strict: bool, limit: usize, size: &mut u64) -> Result<String, HttpRequestError> {
+    let mut line = vec![];
+    // +2 for the line ending
+    let limit = limit as u64 + 2;
+    let read = (&mut *conn).take(limit).read_until(b'\n', &mut line).await?;
+    *size += read as u64;
+    if line.last() != Some(&b'\n') {
+        if read as u64 == limit { return Err(HttpRequestError::TooLong); }
+        // will return if connection is shut down without \n
+        return Err(HttpRequestError::EarlyEof);
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    } else if strict {
+        return Err(HttpRequestError::InvalidLineEnding);
+    }
+    if strict && line.contains(&b'\r') { return Err(HttpRequestError::InvalidLineEnding); }
+    Ok(String::from_utf8(line)?)
+}
+
+/// Content-Length is 1*DIGIT (`parse` would accept a leading `+`)
+fn parse_len(len: &str) -> Result<u64, HttpRequestError> {
+    if len.is_empty() || !len.bytes().all(|c| c.is_ascii_digit()) { return Err(HttpRequestError::InvalidLength); }
+    len.parse().map_err(|_| HttpRequestError::InvalidLength)
+}
+
+/// Checks `host[:port]` of the authority-form and absolute-form
+///
+/// Userinfo (`user@host`) is rejected, it's deprecated in HTTP
+fn is_authority(authority: &str) -> bool {
+    !authority.is_empty() && !authority.contains(['@', '/', '?', '#'])
+}
+
+/// Splits `scheme://authority/path?query` into authority and path
+fn split_absolute(target: &str) -> Option<(&str, String)> {
+    let (scheme, rest) = target.split_once("://")?;
+    let mut chars = scheme.chars();
+    if !chars.next()?.is_ascii_alphabetic() { return None; }
+    if !chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')) { return None; }
+
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    if !is_authority(authority) { return None; }
+    // `http://host` and `http://host?query` have an empty path, which means `/`
+    let path = if path.starts_with('/') { path.to_string() } else { format!("/{path}") };
+    Some((authority, path))
+}
+
+/// Recognizes the form of request-target and converts it into a route
+///
+/// Returns the authority for absolute-form and authority-form
+fn parse_target(method: &HttpMethod, target: &str, strict: bool) -> Result<(RequestTarget, String, Option<String>), HttpRequestError> {
+    if target.starts_with('/') {
+        Ok((RequestTarget::Origin, target.to_string(), None))
+    } else if target == "*" {
+        // Asterisk is meant for OPTIONS (and the HTTP/2 preface), but SSDP also uses it with its own methods
+        let allowed = *method == HttpMethod::Options || *method == HttpMethod::Other("PRI".to_string());
+        if strict && !allowed { return Err(HttpRequestError::InvalidTarget); }
+        Ok((RequestTarget::Asterisk, "*".to_string(), None))
+    } else if *method == HttpMethod::Connect {
+        if !is_authority(target) || !target.contains(':') { return Err(HttpRequestError::InvalidTarget); }
+        Ok((RequestTarget::Authority, "/".to_string(), Some(target.to_string())))
+    } else {
+        let (authority, path) = split_absolute(target).ok_or(HttpRequestError::InvalidTarget)?;
+        Ok((RequestTarget::Absolute, path, Some(authority.to_string())))
+    }
+}
+
+/// Room for method and version in the first line
+const PRELUDE_EXTRA: usize = 64;
+
+/// Reads a request from the provided stream
+///
+/// Limits are taken from the server. In strict mode, anything ambiguous according to RFC 9112 is rejected
+pub(crate) async fn read(mut conn: impl HttpRead, server: &HttpServer) -> Result<HttpRequest, HttpRequestError> {
+    let strict = server.strict;
+
+    // get first line
+    // everything up to the empty line counts towards `max_headers_size`
+    let mut size = 0;
+    let first = read_line(&mut conn, strict, server.max_uri_len + PRELUDE_EXTRA, &mut size).await.map_err(|err| match err {
+        HttpRequestError::TooLong => HttpRequestError::UriTooLong,
+        err => err,
+    })?;
+    // and slice it by 3 components
+    let (method, route, version) = if strict {
+        split3_strict(&first)?
+    } else {
+        split3(&first).ok_or(HttpRequestError::InvalidPrelude)?
+    };
+    // then parse method, allocate route, parse version
+    let method = HttpMethod::new(method);
+    if route.len() > server.max_uri_len { return Err(HttpRequestError::UriTooLong); }
+    let raw_target = route.to_string();
+    let (target, route, authority) = parse_target(&method, route, strict)?;
+    // CONNECT always has an authority-form target
+    if strict && method == HttpMethod::Connect && target != RequestTarget::Authority {
+        return Err(HttpRequestError::InvalidTarget);
+    }
+    let version = if strict { parse_ver_strict(version) } else { parse_ver(version) };
+    let version = version.ok_or(HttpRequestError::InvalidVersion)?;
+    // read headers
+    let mut headers = vec![];
+    loop {
+        let line = read_line(&mut conn, strict, server.max_header_line, &mut size).await.map_err(|err| match err {
+            HttpRequestError::TooLong => HttpRequestError::HeaderTooLong,
+            err => err,
+        })?;
+        if size > server.max_headers_size { return Err(HttpRequestError::TooLong); }
+        if line.is_empty() {
+            // empty line = end of request
+            break;
+        }
+        if headers.len() >= server.max_header_count { return Err(HttpRequestError::TooManyHeaders); }
+        if strict {
+            headers.push(parse_header_strict(&line)?);
+        } else {
+            headers.push(parse_header(&line).ok_or(HttpRequestError::InvalidHeader)?);
+        }
+    }
+
+    let addr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+    let trailers = Arc::new(OnceLock::new());
+    let mut req = HttpRequest { method, route, target, raw_target, version, headers, len: Some(0), addr, port: 0, scheme: "http".to_string(), host: None, id: String::new(), trailers };
+
+    // HTTP/1.1 requires exactly one Host
+    if strict && req.version.is(1, 1) && req.get_headers("Host").count() != 1 {
+        return Err(HttpRequestError::InvalidHost);
+    }
+
+    // Authority in the request line takes precedence over Host
+    if let Some(authority) = authority {
+        req.headers.retain(|h| !h.name.eq_ignore_ascii_case("Host"));
+        req.headers.push(HttpHeader { name: "Host".to_string(), value: authority });
+    }
+
+    // Content-Length may be repeated or be a list, but all values must be the same
+    let mut len = None;
+    for value in req.get_headers("Content-Length").flat_map(|v| v.split(',')) {
+        let value = parse_len(value.trim())?;
+        if len.is_some_and(|len| len != value || strict) {
+            return Err(HttpRequestError::AmbiguousLength);
+        }
+        len = Some(value);
+    }
+
+    // Transfer-Encoding overrides Content-Length
+    let encodings: Vec<&str> = req.get_headers("Transfer-Encoding").flat_map(|v| v.split(',')).collect();
+    if !encodings.is_empty() {
+        // chunked is the only coding we can decode
+        if encodings.len() != 1 || !encodings[0].trim().eq_ignore_ascii_case("chunked") {
+            return Err(HttpRequestError::InvalidEncoding);
+        }
+        // Having both is a request smuggling attempt, and HTTP/1.0 does not have Transfer-Encoding
+        if strict && (len.is_some() || req.version.is(1, 0)) {
+            return Err(HttpRequestError::AmbiguousLength);
+        }
+        req.len = None;
+    } else {
+        req.len = Some(len.unwrap_or(0));
+    }
+
+    // Only chunked bodies can have trailers
+    if req.len.is_some() {
+        let _ = req.trailers.set(vec![]);
+    }
+
+    Ok(req)
+}
+
+/// Can the response to this request use chunked encoding? (HTTP/1.1 and up)
+pub(crate) fn can_chunk(req: &HttpRequest) -> bool {
+    req.version.major > 1 || (req.version.major == 1 && req.version.minor >= 1)
+}
+
+/// Checks if a comma-separated header value (like `Connection` or `TE`) contains a token
+///
+/// Parameters after `;` are ignored
+pub(crate) fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|t| t.split(';').next().unwrap().trim().eq_ignore_ascii_case(token))
+}
+
+/// Headers that are never stripped, even if listed in `Connection`
+///
+/// `Upgrade` is kept to allow services to implement protocol upgrades.
+/// `TE` must be listed there by clients that send `TE: trailers` (RFC 9110 10.1.4),
+/// and the rest are needed to read the request
+const KEEP_HEADERS: &[&str] = &["upgrade", "connection", "te", "host", "content-length", "transfer-encoding"];
+
+/// Removes headers listed in `Connection`, except [`KEEP_HEADERS`]
+pub(crate) fn strip_connection_headers(headers: &mut Vec<HttpHeader>) {
+    let named: Vec<String> = headers.iter()
+        .filter(|h| h.name.eq_ignore_ascii_case("Connection"))
+        .flat_map(|h| h.value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !KEEP_HEADERS.contains(&name.as_str()))
+        .collect();
+    if named.is_empty() { return; }
+    headers.retain(|h| !named.contains(&h.name.to_ascii_lowercase()));
+}
+
+/// Send the request
+/// Body is taken from the response, the rest is kept for the logger
+pub(crate) async fn send(req: &HttpRequest, res: &mut HttpResponse, conn: &mut Timeout<impl HttpConnection>) -> io::Result<()> {
+    let mut body = std::mem::replace(&mut res.body, HttpBody::Bytes(vec![]));
+    let chunked = can_chunk(req);
+    // Trailers are only sent if client has asked for them
+    let send_trailers = chunked && req.get_header("TE").is_some_and(|te| has_token(te, "trailers"));
+
+    if send_trailers && !res.trailers.is_empty() {
+        // Trailers can only be sent after a chunked body
+        body = match body {
+            HttpBody::Bytes(bytes) => HttpBody::Stream(Box::new(BytesStream(Some(bytes)))),
+            HttpBody::File { file, len } => HttpBody::Stream(Box::new(ReaderStream { reader: file.take(len) })),
+            body => body,
+        };
+        let names: Vec<&str> = res.trailers.iter().map(|t| t.name.as_str()).collect();
+        res.add_header("Trailer", names.join(", "));
+    }
+
+    let code = res.code;
+    let status = code.as_str();
+    let mut buf = format!("HTTP/1.1 {code} {status}\r\n");
+
+    for header in &res.headers {
+        write!(&mut buf, "{}: {}\r\n", &header.name, &header.value).unwrap();
+    }
+
+    if !res.content_type.is_empty() {
+        write!(&mut buf, "Content-Type: {}\r\n", &res.content_type).unwrap();
+    }
+
+    match &body {
+        HttpBody::Bytes(bytes) => write!(&mut buf, "Content-Length: {}\r\n", bytes.len()).unwrap(),
+        HttpBody::File { len, .. } => write!(&mut buf, "Content-Length: {}\r\n", len).unwrap(),
+        // HTTP/1.0 gets a close-delimited body
+        HttpBody::Stream(_) if chunked => buf.push_str("Transfer-Encoding: chunked\r\n"),
+        HttpBody::Stream(_) | HttpBody::Upgrade(_) => {},
+    };
+    buf.push_str("\r\n");
+
+    // Send headers, only the body is counted
+    conn.written = 0;
+    conn.write_all(buf.as_bytes()).await?;
+    conn.written = 0;
+
+    // Don't send body on head requests
+    if req.method == HttpMethod::Head { return Ok(()); }
+
+    // Now, handle the body
+    match body {
+        HttpBody::Bytes(bytes) => {
+            conn.write_all(&bytes).await?;
+        }
+        HttpBody::File { file, len } => {
+            conn.send_file(file, len).await?;
+        }
+        HttpBody::Stream(mut stream) => {
+            while let Some(chunk) = stream.next_raw().await? {
+                // empty chunk would mean the end of body
+                if chunk.is_empty() { continue; }
+                if chunked {
+                    let mut buf = format!("{:x}\r\n", chunk.len()).into_bytes();
+                    buf.extend_from_slice(&chunk);
+                    buf.extend_from_slice(b"\r\n");
+                    conn.write_all(&buf).await?;
+                    // chunk framing is not counted
+                    conn.written -= (buf.len() - chunk.len()) as u64;
+                } else {
+                    conn.write_all(&chunk).await?;
+                }
+            }
+            if chunked {
+                let mut buf = "0\r\n".to_string();
+                if send_trailers {
+                    res.trailers.extend(stream.trailers_raw());
+                    for trailer in &res.trailers {
+                        write!(&mut buf, "{}: {}\r\n", &trailer.name, &trailer.value).unwrap();
+                    }
+                }
+                buf.push_str("\r\n");
+                let written = conn.written;
+                conn.write_all(buf.as_bytes()).await?;
+                conn.written = written;
+            }
+        }
+        HttpBody::Upgrade(mut handler) => {
+            handler.upgrade_raw(conn).await?;
+            conn.shutdown().await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Error when parsing an HTTP/1.1 request.
+/// For debugging purposes only
+#[derive(Debug)]
+pub(crate) enum HttpRequestError {
+    /// IO error
+    Io(io::Error),
+    /// Some part of request contained invalid Unicode
+    NotUnicode(FromUtf8Error),
+    /// Request exceed its size limit
+    TooLong,
+    /// Request line was longer than the limit
+    UriTooLong,
+    /// Header line was longer than the limit
+    HeaderTooLong,
+    /// Request has too many headers
+    TooManyHeaders,
+    /// Request ended too early
+    EarlyEof,
+    /// First line didn't follow the `METHOD /route HTTP/1.1` format
+    InvalidPrelude,
+    /// Could not parse HTTP version
+    InvalidVersion,
+    /// Method was not a token
+    InvalidMethod,
+    /// Header line did not contain a colon, or contained invalid characters
+    InvalidHeader,
+    /// Header line started with whitespace (obsolete line folding)
+    ObsFold,
+    /// Line did not end with `\r\n` or contained a bare `\r`
+    InvalidLineEnding,
+    /// Request-target is not in any of the known forms
+    InvalidTarget,
+    /// HTTP/1.1 request did not have exactly one `Host` header
+    InvalidHost,
+    /// Conflicting `Content-Length` headers, or `Content-Length` with `Transfer-Encoding`
+    AmbiguousLength,
+    /// `Content-Length` header did not contain a number
+    InvalidLength,
+    /// `Transfer-Encoding` was something other than `chunked`
+    InvalidEncoding,
+}
+
+impl HttpRequestError {
+    /// Status code to respond with
+    pub(crate) fn status_code(&self) -> StatusCode {
+        match self {
+            HttpRequestError::UriTooLong => StatusCode::URI_TOO_LONG,
+            HttpRequestError::TooLong
+            | HttpRequestError::HeaderTooLong
+            | HttpRequestError::TooManyHeaders => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for HttpRequestError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpRequestError::Io(err) => err.fmt(fmt),
+            HttpRequestError::NotUnicode(err) => err.fmt(fmt),
+            HttpRequestError::TooLong => fmt.write_str("request too long"),
+            HttpRequestError::UriTooLong => fmt.write_str("uri too long"),
+            HttpRequestError::HeaderTooLong => fmt.write_str("header too long"),
+            HttpRequestError::TooManyHeaders => fmt.write_str("too many headers"),
+            HttpRequestError::EarlyEof => fmt.write_str("incomplete request"),
+            // first line of request did not contain exactly 3 elements (method, path and version)
+            HttpRequestError::InvalidPrelude => fmt.write_str("invalid prelude"),
+            HttpRequestError::InvalidVersion => fmt.write_str("invalid http version"),
+            HttpRequestError::InvalidMethod => fmt.write_str("invalid method"),
+            HttpRequestError::InvalidHeader => fmt.write_str("invalid header"),
+            HttpRequestError::ObsFold => fmt.write_str("obsolete line folding"),
+            HttpRequestError::InvalidLineEnding => fmt.write_str("line without CRLF or with a bare CR"),
+            HttpRequestError::InvalidTarget => fmt.write_str("invalid request target"),
+            HttpRequestError::InvalidHost => fmt.write_str("missing or repeated host header"),
+            HttpRequestError::AmbiguousLength => fmt.write_str("ambiguous body length"),
+            HttpRequestError::InvalidLength => fmt.write_str("content-length header did not contain a number"),
+            HttpRequestError::InvalidEncoding => fmt.write_str("unsupported transfer-encoding"),
+        }
+    }
+}
+
+impl From<FromUtf8Error> for HttpRequestError {
+    fn from(err: FromUtf8Error) -> HttpRequestError {
+        HttpRequestError::NotUnicode(err)
+    }
+}
+
+impl From<io::Error> for HttpRequestError {
+    fn from(err: io::Error) -> HttpRequestError {
+        if err.kind() == ErrorKind::UnexpectedEof {
+            HttpRequestError::TooLong
+        } else {
+            HttpRequestError::Io(err)
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Request parser conformance corpus
+//!
+//! Every case is parsed in both lenient and strict mode
+
+use super::*;
+
+/// Parsed successfully
+const OK: bool = true;
+/// Rejected with 400
+const BAD: bool = false;
+
+/// (request, lenient, strict)
+const CORPUS: &[(&str, bool, bool)] = &[
+    // valid requests
+    ("GET / HTTP/1.1\r\nHost: a\r\n\r\n", OK, OK),
+    ("GET /path?query=1 HTTP/1.1\r\nHost: a\r\nUser-Agent: curl/8.0\r\n\r\n", OK, OK),
+    ("GET / HTTP/1.0\r\n\r\n", OK, OK),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\n", OK, OK),
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n", OK, OK),
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: CHUNKED\r\n\r\n", OK, OK),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX-Empty:\r\n\r\n", OK, OK),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX-Tab:\tvalue\t\r\n\r\n", OK, OK),
+
+    // request line
+    ("GET  / HTTP/1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("GET\t/ HTTP/1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("GET / HTTP/1.1 \r\nHost: a\r\n\r\n", OK, BAD),
+    (" GET / HTTP/1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("G(T / HTTP/1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("GE\"T / HTTP/1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("GET / extra HTTP/1.1\r\nHost: a\r\n\r\n", BAD, BAD),
+    ("GET /\x7f HTTP/1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("GET / HTTP/1.1.1\r\nHost: a\r\n\r\n", BAD, BAD),
+    ("GET / HTTP/01.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("GET / HTTP/+1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("GET / http/1.1\r\nHost: a\r\n\r\n", BAD, BAD),
+    ("GET /\r\n\r\n", BAD, BAD),
+
+    // request target
+    ("GET http://a/b HTTP/1.1\r\nHost: a\r\n\r\n", OK, OK),
+    ("OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n", OK, OK),
+    ("M-SEARCH * HTTP/1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("GET * HTTP/1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("PRI * HTTP/2.0\r\n\r\n", OK, OK),
+    ("CONNECT a:443 HTTP/1.1\r\nHost: a:443\r\n\r\n", OK, OK),
+    ("CONNECT / HTTP/1.1\r\nHost: a\r\n\r\n", OK, BAD),
+    ("CONNECT a HTTP/1.1\r\nHost: a\r\n\r\n", BAD, BAD),
+    ("GET a:443 HTTP/1.1\r\nHost: a\r\n\r\n", BAD, BAD),
+    ("GET path HTTP/1.1\r\nHost: a\r\n\r\n", BAD, BAD),
+    ("GET http:///path HTTP/1.1\r\nHost: a\r\n\r\n", BAD, BAD),
+    ("GET http://user@a/ HTTP/1.1\r\nHost: a\r\n\r\n", BAD, BAD),
+    ("GET 1http://a/ HTTP/1.1\r\nHost: a\r\n\r\n", BAD, BAD),
+
+    // line endings
+    ("GET / HTTP/1.1\nHost: a\n\n", OK, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\n\r\n", OK, BAD),
+    ("GET / HTTP/1.1\rHost: a\r\n\r\n", BAD, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX-A: b\rc\r\n\r\n", OK, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\n", BAD, BAD),
+
+    // header syntax
+    ("GET / HTTP/1.1\r\nHost: a\r\nNo colon\r\n\r\n", BAD, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX-A : b\r\n\r\n", BAD, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX-A\t: b\r\n\r\n", BAD, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\n: empty name\r\n\r\n", OK, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX A: b\r\n\r\n", OK, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX-A: b\x00c\r\n\r\n", OK, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX-A: b\x1bc\r\n\r\n", OK, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX-A: b\r\n c\r\n\r\n", BAD, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\nX-A: b\r\n\tc: d\r\n\r\n", OK, BAD),
+
+    // Host
+    ("GET / HTTP/1.1\r\n\r\n", OK, BAD),
+    ("GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n", OK, BAD),
+
+    // Content-Length
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: abc\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: +5\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: -5\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 0x5\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 99999999999999999999\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length:\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\n", OK, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5, 5\r\n\r\n", OK, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5, 6\r\n\r\n", BAD, BAD),
+
+    // Transfer-Encoding
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n", OK, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n", OK, BAD),
+    ("POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n", OK, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked, chunked\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: xchunked\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding : chunked\r\n\r\n", BAD, BAD),
+    ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding:\r\n\r\n", BAD, BAD),
+];
+
+fn parse_with(input: &str, server: &HttpServer) -> Result<HttpRequest, HttpRequestError> {
+    tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(read(input.as_bytes(), server))
+}
+
+fn parse(input: &str, strict: bool) -> Result<HttpRequest, HttpRequestError> {
+    let mut server = HttpServer::new();
+    server.strict = strict;
+    parse_with(input, &server)
+}
+
+#[test]
+fn corpus() {
+    for &(input, lenient, strict) in CORPUS {
+        let result = parse(input, false);
+        assert_eq!(result.is_ok(), lenient, "lenient {input:?}: {result:?}");
+        let result = parse(input, true);
+        assert_eq!(result.is_ok(), strict, "strict {input:?}: {result:?}");
+    }
+}
+
+#[test]
+fn body_length() {
+    let req = parse("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5, 5\r\n\r\n", false).unwrap();
+    assert_eq!(req.len, Some(5));
+    let req = parse("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n", false).unwrap();
+    assert_eq!(req.len, None);
+    assert!(req.trailers().is_none());
+    let req = parse("GET / HTTP/1.1\r\nHost: a\r\n\r\n", true).unwrap();
+    assert_eq!(req.len, Some(0));
+    assert!(req.trailers().is_some_and(|t| t.is_empty()));
+}
+
+#[test]
+fn header_values() {
+    let req = parse("GET / HTTP/1.1\r\nHost: a\r\nX-Tab:\t value \t\r\nX-Empty:\r\n\r\n", true).unwrap();
+    assert_eq!(req.get_header("x-tab"), Some("value"));
+    assert_eq!(req.get_header("X-Empty"), Some(""));
+    assert_eq!(req.version.major, 1);
+    assert_eq!(req.version.minor, 1);
+}
+
+#[test]
+fn connection_headers() {
+    let mut req = parse("GET / HTTP/1.1\r\nHost: a\r\nConnection: keep-alive, X-Hop, upgrade\r\nKeep-Alive: 5\r\nx-hop: 1\r\nUpgrade: websocket\r\nX-End: 1\r\n\r\n", true).unwrap();
+    strip_connection_headers(&mut req.headers);
+    let names: Vec<&str> = req.headers.iter().map(|h| h.name.as_str()).collect();
+    assert_eq!(names, ["Host", "Connection", "Upgrade", "X-End"]);
+
+    // needed by us, so they are kept
+    let mut req = parse("GET / HTTP/1.1\r\nHost: a\r\nConnection: TE, Host\r\nTE: trailers\r\n\r\n", true).unwrap();
+    strip_connection_headers(&mut req.headers);
+    assert_eq!(req.get_header("TE"), Some("trailers"));
+    assert_eq!(req.get_header("Host"), Some("a"));
+}
+
+#[test]
+fn tokens() {
+    assert!(has_token("keep-alive, Upgrade", "upgrade"));
+    assert!(has_token("trailers;q=1, deflate", "trailers"));
+    assert!(!has_token("trailersx", "trailers"));
+    assert!(!has_token("", "close"));
+}
+
+#[test]
+fn limits() {
+    let mut server = HttpServer::new();
+    server.max_uri_len = 16;
+    server.max_header_line = 32;
+    server.max_header_count = 3;
+    server.max_headers_size = 80;
+
+    let code = |input: &str| parse_with(input, &server).map(|_| 200).unwrap_or_else(|e| e.status_code().0);
+    assert_eq!(code("GET /0123456789abcde HTTP/1.1\r\n\r\n"), 200);
+    assert_eq!(code("GET /0123456789abcdef HTTP/1.1\r\n\r\n"), 414);
+    assert_eq!(code(&format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(1000))), 414);
+    assert_eq!(code("GET / HTTP/1.1\r\nX-Header: 0123456789abcdefghijkl\r\n\r\n"), 200);
+    assert_eq!(code("GET / HTTP/1.1\r\nX-Header: 0123456789abcdefghijklm\r\n\r\n"), 431);
+    assert_eq!(code("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"), 200);
+    assert_eq!(code("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n"), 431);
+    assert_eq!(code("GET / HTTP/1.1\r\nA: 0123456789abcdefghijklmnopqrst\r\nB: 0123456789abcdefghijklmnopqrst\r\n\r\n"), 431);
+    // request line and line endings count too: 80 bytes, and 86 with the last header
+    assert_eq!(code("GET /0123456789abcde HTTP/1.1\r\nA: 0123456789abcdefghijklmnopq\r\nB: 0123456789\r\n\r\n"), 200);
+    assert_eq!(code("GET /0123456789abcde HTTP/1.1\r\nA: 0123456789abcdefghijklmnopq\r\nB: 0123456789\r\nC: 1\r\n\r\n"), 431);
+    assert_eq!(code("GET / HTTP/1.1\r\nA: 1\r\n"), 400);
+}
+
+#[test]
+fn targets() {
+    let cases = [
+        ("GET /a?b HTTP/1.1\r\nHost: h\r\n\r\n", RequestTarget::Origin, "/a?b", "h"),
+        ("GET http://example.com:8080/a?b HTTP/1.1\r\nHost: h\r\n\r\n", RequestTarget::Absolute, "/a?b", "example.com:8080"),
+        ("GET HTTPS://example.com HTTP/1.1\r\nHost: h\r\n\r\n", RequestTarget::Absolute, "/", "example.com"),
+        ("GET http://example.com?q HTTP/1.1\r\n\r\n", RequestTarget::Absolute, "/?q", "example.com"),
+        ("CONNECT [::1]:443 HTTP/1.1\r\nHost: h\r\n\r\n", RequestTarget::Authority, "/", "[::1]:443"),
+        ("OPTIONS * HTTP/1.1\r\nHost: h\r\n\r\n", RequestTarget::Asterisk, "*", "h"),
+    ];
+    for (input, target, route, host) in cases {
+        let req = parse(input, false).unwrap();
+        assert_eq!(req.target, target, "{input:?}");
+        assert_eq!(req.route, route, "{input:?}");
+        assert_eq!(req.raw_target, input.split(' ').nth(1).unwrap(), "{input:?}");
+        assert_eq!(req.get_headers("Host").collect::<Vec<_>>(), [host], "{input:?}");
+    }
+}
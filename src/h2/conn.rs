@@ -0,0 +1,481 @@
+//! HTTP/2 connection: reads frames, runs streams and writes their output
+
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, ready};
+
+use tokio::io::AsyncWrite;
+use tokio::time::Sleep;
+
+use crate::core::connection::{HttpConnection, Timeout};
+use crate::h2::frame::{self, Frame, FrameReader, ReadError, ErrorCode};
+use crate::h2::hpack::{self, HpackError};
+use crate::h2::stream::{Shared, Stream, StreamIo, STREAM_WINDOW, CONN_WINDOW, OUT_LIMIT};
+use crate::h2::{Rejected, make_request, make_trailers, run_stream};
+use crate::reqres::{HttpRequest, StatusCode};
+use crate::server::HttpServer;
+
+type StreamFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// Advertised in `SETTINGS_MAX_CONCURRENT_STREAMS`
+const MAX_CONCURRENT_STREAMS: usize = 100;
+/// Default `SETTINGS_HEADER_TABLE_SIZE`, we don't change it
+const HEADER_TABLE_SIZE: usize = 4096;
+/// Clients that make us queue more control frames than this without reading them are flooding us
+/// (like PING and SETTINGS floods, CVE-2019-9512 and CVE-2019-9515)
+const MAX_QUEUED_CONTROL: usize = 1000;
+
+/// Header block that is being received in `CONTINUATION` frames
+struct HeaderBlock {
+    stream: u32,
+    end_stream: bool,
+    block: Vec<u8>,
+}
+
+/// Serves an HTTP/2 connection after the preface, until it's closed
+pub(crate) struct Connection<'a, T: HttpConnection> {
+    server: &'a HttpServer,
+    conn: &'a mut Timeout<T>,
+    reader: FrameReader,
+    decoder: hpack::Decoder,
+    shared: Arc<Mutex<Shared>>,
+    /// Running services
+    streams: Vec<(u32, StreamFuture<'a>)>,
+    /// Output that is being written, taken from `Shared::out`
+    writing: Vec<u8>,
+    written: usize,
+    continuation: Option<HeaderBlock>,
+    /// Highest stream opened by the client
+    last_stream: u32,
+    settings_received: bool,
+    /// Number of streams served
+    requests: usize,
+    /// No new streams are accepted (GOAWAY was sent or received)
+    goaway: bool,
+    goaway_sent: bool,
+    /// Nothing is read anymore (client has closed the connection, or it has failed)
+    read_closed: bool,
+    idle: Option<Pin<Box<Sleep>>>,
+    /// Completes when server is shutting down
+    shutdown: Pin<Box<dyn Future<Output = ()> + Send + 'a>>,
+    peer: Option<SocketAddr>,
+    secure: bool,
+}
+
+impl<'a, T: HttpConnection> Connection<'a, T> {
+    pub(crate) fn new(server: &'a HttpServer, conn: &'a mut Timeout<T>) -> Connection<'a, T> {
+        let mut shared = Shared::new();
+        frame::write_settings(&mut shared.out, &[
+            (frame::SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS as u32),
+            (frame::SETTINGS_INITIAL_WINDOW_SIZE, STREAM_WINDOW as u32),
+            (frame::SETTINGS_MAX_HEADER_LIST_SIZE, server.max_headers_size.min(u32::MAX as u64) as u32),
+        ]);
+        // connection window can only be changed with WINDOW_UPDATE
+        frame::write_window_update(&mut shared.out, 0, (CONN_WINDOW - 65535) as u32);
+
+        Connection {
+            server,
+            peer: conn.getpeername().ok(),
+            secure: conn.is_secure(),
+            conn,
+            reader: FrameReader::new(),
+            decoder: hpack::Decoder::new(HEADER_TABLE_SIZE),
+            shared: Arc::new(Mutex::new(shared)),
+            streams: vec![],
+            writing: vec![],
+            written: 0,
+            continuation: None,
+            last_stream: 0,
+            settings_received: false,
+            requests: 0,
+            goaway: false,
+            goaway_sent: false,
+            read_closed: false,
+            idle: None,
+            shutdown: Box::pin(server.shutdown.wait()),
+        }
+    }
+
+    /// Applies client's settings
+    pub(crate) fn apply_settings(&mut self, settings: &[(u16, u32)]) -> Result<(), ErrorCode> {
+        let mut shared = self.shared.lock().unwrap();
+        for &(id, value) in settings {
+            match id {
+                frame::SETTINGS_ENABLE_PUSH if value > 1 => return Err(ErrorCode::PROTOCOL_ERROR),
+                frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > frame::MAX_WINDOW { return Err(ErrorCode::FLOW_CONTROL_ERROR); }
+                    // change applies to all streams
+                    let delta = value - shared.initial_window;
+                    shared.initial_window = value;
+                    for stream in shared.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > frame::MAX_WINDOW { return Err(ErrorCode::FLOW_CONTROL_ERROR); }
+                    }
+                    shared.wake_blocked();
+                }
+                frame::SETTINGS_MAX_FRAME_SIZE => {
+                    if !(16384..=16777215).contains(&value) { return Err(ErrorCode::PROTOCOL_ERROR); }
+                    shared.max_frame_size = value as usize;
+                }
+                // encoder does not use the dynamic table
+                frame::SETTINGS_HEADER_TABLE_SIZE => {}
+                // other settings are about server push
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a new stream and its service
+    pub(crate) fn open(&mut self, id: u32, req: Result<HttpRequest, StatusCode>, end_stream: bool) {
+        let (len, trailers) = match &req {
+            Ok(req) => (req.len, Arc::clone(&req.trailers)),
+            Err(_) => (None, Arc::new(OnceLock::new())),
+        };
+        let req = req.map(|mut req| {
+            // upgraded HTTP/1.1 request has been resolved already, and has its ID
+            if req.id.is_empty() {
+                self.server.set_client(&mut req, self.peer, self.secure);
+            }
+            req
+        });
+
+        let mut shared = self.shared.lock().unwrap();
+        let mut stream = Stream::new(STREAM_WINDOW, shared.initial_window, len, trailers);
+        if end_stream {
+            stream.close_recv();
+        }
+        shared.streams.insert(id, stream);
+        drop(shared);
+
+        self.requests += 1;
+        let io = StreamIo::new(id, Arc::clone(&self.shared), self.peer, self.secure);
+        self.streams.push((id, Box::pin(run_stream(self.server, req, io, self.requests))));
+        self.last_stream = self.last_stream.max(id);
+
+        if self.server.max_requests.is_some_and(|max| self.requests >= max) {
+            self.go_away(ErrorCode::NO_ERROR);
+        }
+    }
+
+    /// Stops accepting new streams
+    fn go_away(&mut self, code: ErrorCode) {
+        self.goaway = true;
+        if self.goaway_sent { return; }
+        self.goaway_sent = true;
+        frame::write_goaway(&mut self.shared.lock().unwrap().out, self.last_stream, code);
+    }
+
+    /// Closes the connection because of an error
+    pub(crate) fn fail(&mut self, code: ErrorCode) {
+        self.go_away(code);
+        self.read_closed = true;
+        self.shared.lock().unwrap().closed = true;
+        // drops the services
+        self.streams.clear();
+    }
+
+    fn handle(&mut self, mut frame: Frame) -> Result<(), ErrorCode> {
+        // Preface has to be followed by SETTINGS
+        if !self.settings_received && (frame.kind != frame::SETTINGS || frame.has(frame::ACK)) {
+            return Err(ErrorCode::PROTOCOL_ERROR);
+        }
+        // Header block can't be interrupted by other frames
+        if let Some(block) = &self.continuation && (frame.kind != frame::CONTINUATION || frame.stream != block.stream) {
+            return Err(ErrorCode::PROTOCOL_ERROR);
+        }
+
+        match frame.kind {
+            frame::DATA => self.on_data(frame)?,
+            frame::HEADERS => {
+                if frame.stream == 0 || frame.stream.is_multiple_of(2) { return Err(ErrorCode::PROTOCOL_ERROR); }
+                frame.unpad()?;
+                if frame.has(frame::PRIORITY_FLAG) {
+                    // priority is ignored
+                    if frame.payload.len() < 5 { return Err(ErrorCode::FRAME_SIZE_ERROR); }
+                    frame.payload.drain(..5);
+                }
+                let block = HeaderBlock { stream: frame.stream, end_stream: frame.has(frame::END_STREAM), block: frame.payload };
+                if frame.flags & frame::END_HEADERS != 0 {
+                    self.on_headers(block)?;
+                } else {
+                    self.continuation = Some(block);
+                }
+            }
+            frame::CONTINUATION => {
+                let Some(mut block) = self.continuation.take() else { return Err(ErrorCode::PROTOCOL_ERROR) };
+                block.block.extend_from_slice(&frame.payload);
+                // endless CONTINUATION frames would exhaust the memory
+                if block.block.len() as u64 > self.server.max_headers_size { return Err(ErrorCode::ENHANCE_YOUR_CALM); }
+                if frame.has(frame::END_HEADERS) {
+                    self.on_headers(block)?;
+                } else {
+                    self.continuation = Some(block);
+                }
+            }
+            frame::PRIORITY => {
+                if frame.stream == 0 { return Err(ErrorCode::PROTOCOL_ERROR); }
+                if frame.payload.len() != 5 {
+                    self.shared.lock().unwrap().reset(frame.stream, ErrorCode::FRAME_SIZE_ERROR);
+                }
+            }
+            frame::RST_STREAM => {
+                if frame.stream == 0 || frame.stream > self.last_stream { return Err(ErrorCode::PROTOCOL_ERROR); }
+                if frame.payload.len() != 4 { return Err(ErrorCode::FRAME_SIZE_ERROR); }
+                self.shared.lock().unwrap().abort(frame.stream);
+            }
+            frame::SETTINGS => {
+                if frame.stream != 0 { return Err(ErrorCode::PROTOCOL_ERROR); }
+                if frame.has(frame::ACK) {
+                    if !frame.payload.is_empty() { return Err(ErrorCode::FRAME_SIZE_ERROR); }
+                    return Ok(());
+                }
+                self.apply_settings(&frame::parse_settings(&frame.payload)?)?;
+                self.settings_received = true;
+                let mut shared = self.shared.lock().unwrap();
+                frame::write(&mut shared.out, frame::SETTINGS, frame::ACK, 0, &[]);
+                shared.control += 1;
+            }
+            frame::PUSH_PROMISE => return Err(ErrorCode::PROTOCOL_ERROR),
+            frame::PING => {
+                if frame.stream != 0 { return Err(ErrorCode::PROTOCOL_ERROR); }
+                if frame.payload.len() != 8 { return Err(ErrorCode::FRAME_SIZE_ERROR); }
+                if !frame.has(frame::ACK) {
+                    let mut shared = self.shared.lock().unwrap();
+                    frame::write(&mut shared.out, frame::PING, frame::ACK, 0, &frame.payload);
+                    shared.control += 1;
+                }
+            }
+            frame::GOAWAY => {
+                if frame.stream != 0 { return Err(ErrorCode::PROTOCOL_ERROR); }
+                // client won't open new streams, the connection ends after the current ones
+                self.goaway = true;
+            }
+            frame::WINDOW_UPDATE => self.on_window_update(frame)?,
+            // unknown frames are ignored
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn on_data(&mut self, mut frame: Frame) -> Result<(), ErrorCode> {
+        let id = frame.stream;
+        if id == 0 || id > self.last_stream { return Err(ErrorCode::PROTOCOL_ERROR); }
+
+        // padding counts too
+        let len = frame.payload.len();
+        let mut shared = self.shared.lock().unwrap();
+        shared.recv_window -= len as i64;
+        if shared.recv_window < 0 { return Err(ErrorCode::FLOW_CONTROL_ERROR); }
+        frame.unpad()?;
+        let data = frame.payload;
+
+        let Some(stream) = shared.streams.get_mut(&id) else {
+            // stream has ended, data is dropped
+            shared.release(id, len);
+            return Ok(());
+        };
+        let error = if stream.reset {
+            None
+        } else if stream.recv_closed {
+            Some(ErrorCode::STREAM_CLOSED)
+        } else if stream.recv_window < len as i64 {
+            Some(ErrorCode::FLOW_CONTROL_ERROR)
+        } else {
+            stream.recv_window -= len as i64;
+            stream.received += data.len() as u64;
+            let end = frame.flags & frame::END_STREAM != 0;
+            // content-length has to match the body
+            if stream.len.is_some_and(|l| stream.received > l || (end && stream.received != l)) {
+                Some(ErrorCode::PROTOCOL_ERROR)
+            } else {
+                let padding = len - data.len();
+                if !data.is_empty() {
+                    stream.recv.push_back(data);
+                    stream.wake_reader();
+                }
+                if end {
+                    stream.close_recv();
+                }
+                shared.release(id, padding);
+                return Ok(());
+            }
+        };
+
+        if let Some(code) = error {
+            shared.reset(id, code);
+        }
+        shared.release(id, len);
+        Ok(())
+    }
+
+    fn on_headers(&mut self, block: HeaderBlock) -> Result<(), ErrorCode> {
+        // block has to be decoded even if it's going to be ignored, otherwise the table would be out of sync
+        let fields = match self.decoder.decode(&block.block, self.server.max_headers_size as usize) {
+            Ok(fields) => Ok(fields),
+            Err(HpackError::TooLarge) => Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+            Err(HpackError::Invalid) => return Err(ErrorCode::COMPRESSION_ERROR),
+        };
+        let id = block.stream;
+
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(stream) = shared.streams.get_mut(&id) {
+            // trailers
+            if stream.reset { return Ok(()); }
+            let trailers = fields.ok().and_then(make_trailers);
+            let error = if stream.recv_closed {
+                ErrorCode::STREAM_CLOSED
+            } else if !block.end_stream || stream.len.is_some_and(|l| l != stream.received) {
+                ErrorCode::PROTOCOL_ERROR
+            } else if let Some(trailers) = trailers {
+                let _ = stream.trailers.set(trailers);
+                stream.close_recv();
+                return Ok(());
+            } else {
+                ErrorCode::PROTOCOL_ERROR
+            };
+            shared.reset(id, error);
+            return Ok(());
+        }
+
+        // Stream has ended already, or was sent after GOAWAY
+        if id <= self.last_stream || self.goaway { return Ok(()); }
+
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            self.last_stream = id;
+            frame::write_rst_stream(&mut shared.out, id, ErrorCode::REFUSED_STREAM);
+            shared.control += 1;
+            return Ok(());
+        }
+        drop(shared);
+
+        match fields.map(|fields| make_request(fields, block.end_stream, self.server)) {
+            Ok(Ok(req)) => self.open(id, Ok(req), block.end_stream),
+            Ok(Err(Rejected::Status(code))) | Err(code) => self.open(id, Err(code), block.end_stream),
+            Ok(Err(Rejected::Malformed)) => {
+                self.last_stream = id;
+                let mut shared = self.shared.lock().unwrap();
+                frame::write_rst_stream(&mut shared.out, id, ErrorCode::PROTOCOL_ERROR);
+                shared.control += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, frame: Frame) -> Result<(), ErrorCode> {
+        if frame.payload.len() != 4 { return Err(ErrorCode::FRAME_SIZE_ERROR); }
+        let increment = frame::read_u31(&frame.payload) as i64;
+        let id = frame.stream;
+
+        let mut shared = self.shared.lock().unwrap();
+        if id == 0 {
+            if increment == 0 { return Err(ErrorCode::PROTOCOL_ERROR); }
+            shared.send_window += increment;
+            if shared.send_window > frame::MAX_WINDOW { return Err(ErrorCode::FLOW_CONTROL_ERROR); }
+        } else if id > self.last_stream {
+            return Err(ErrorCode::PROTOCOL_ERROR);
+        } else if let Some(stream) = shared.streams.get_mut(&id) {
+            stream.send_window += increment;
+            if increment == 0 {
+                shared.reset(id, ErrorCode::PROTOCOL_ERROR);
+            } else if stream.send_window > frame::MAX_WINDOW {
+                shared.reset(id, ErrorCode::FLOW_CONTROL_ERROR);
+            }
+        }
+        shared.wake_blocked();
+        Ok(())
+    }
+}
+
+impl<T: HttpConnection> Future for Connection<'_, T> {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        // Read everything that has arrived, unless the client doesn't read our output
+        let mut throttled = false;
+        while !this.read_closed {
+            if this.shared.lock().unwrap().out.len() >= OUT_LIMIT {
+                throttled = true;
+                break;
+            }
+            let Poll::Ready(frame) = this.reader.poll_frame(Pin::new(&mut *this.conn), cx) else { break };
+            let result = match frame {
+                Ok(Some(frame)) => this.handle(frame),
+                Ok(None) => {
+                    // client has closed the connection, responses may still be sent
+                    this.read_closed = true;
+                    this.goaway = true;
+                    Ok(())
+                }
+                Err(ReadError::Protocol(code)) => Err(code),
+                Err(ReadError::Io(err)) => return Poll::Ready(Err(err)),
+            };
+            if let Err(code) = result {
+                this.fail(code);
+            } else if this.shared.lock().unwrap().control > MAX_QUEUED_CONTROL {
+                this.fail(ErrorCode::ENHANCE_YOUR_CALM);
+            }
+        }
+
+        // Drop the services of reset streams
+        let reset: Vec<u32> = {
+            let shared = this.shared.lock().unwrap();
+            this.streams.iter().map(|(id, _)| *id).filter(|id| shared.streams.get(id).is_none_or(|s| s.reset)).collect()
+        };
+        this.streams.retain(|(id, _)| !reset.contains(id));
+
+        // Run the services
+        this.streams.retain_mut(|(_, stream)| stream.as_mut().poll(cx).is_pending());
+
+        // Server is shutting down, the running streams can finish
+        if !this.goaway && this.shutdown.as_mut().poll(cx).is_ready() {
+            this.go_away(ErrorCode::NO_ERROR);
+        }
+
+        // Close the connection if it's idle for too long
+        if this.streams.is_empty() && !this.goaway && let Some(timeout) = this.server.keep_alive_timeout {
+            let idle = this.idle.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+            if idle.as_mut().poll(cx).is_ready() {
+                this.go_away(ErrorCode::NO_ERROR);
+            }
+        } else if !this.streams.is_empty() {
+            this.idle = None;
+        }
+
+        // Write the output
+        loop {
+            if this.written == this.writing.len() {
+                this.writing.clear();
+                this.written = 0;
+                let mut shared = this.shared.lock().unwrap();
+                if shared.out.is_empty() {
+                    // everything has been written out
+                    shared.control = 0;
+                    break;
+                }
+                std::mem::swap(&mut this.writing, &mut shared.out);
+                // there's space for more output
+                shared.wake_blocked();
+            }
+            let written = ready!(Pin::new(&mut *this.conn).poll_write(cx, &this.writing[this.written..]))?;
+            if written == 0 { return Poll::Ready(Err(ErrorKind::WriteZero.into())); }
+            this.written += written;
+        }
+        ready!(Pin::new(&mut *this.conn).poll_flush(cx))?;
+
+        if this.goaway && this.streams.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        // output has drained, reading can go on
+        if throttled {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
@@ -0,0 +1,226 @@
+//! HTTP/2 framing (RFC 9113, section 4 and 6)
+
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::AsyncBufRead;
+
+pub(crate) const DATA: u8 = 0x0;
+pub(crate) const HEADERS: u8 = 0x1;
+pub(crate) const PRIORITY: u8 = 0x2;
+pub(crate) const RST_STREAM: u8 = 0x3;
+pub(crate) const SETTINGS: u8 = 0x4;
+pub(crate) const PUSH_PROMISE: u8 = 0x5;
+pub(crate) const PING: u8 = 0x6;
+pub(crate) const GOAWAY: u8 = 0x7;
+pub(crate) const WINDOW_UPDATE: u8 = 0x8;
+pub(crate) const CONTINUATION: u8 = 0x9;
+
+pub(crate) const END_STREAM: u8 = 0x1;
+pub(crate) const ACK: u8 = 0x1;
+pub(crate) const END_HEADERS: u8 = 0x4;
+pub(crate) const PADDED: u8 = 0x8;
+pub(crate) const PRIORITY_FLAG: u8 = 0x20;
+
+pub(crate) const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub(crate) const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub(crate) const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub(crate) const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub(crate) const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub(crate) const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Error codes of `RST_STREAM` and `GOAWAY`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ErrorCode(pub u32);
+
+impl ErrorCode {
+    pub(crate) const NO_ERROR: ErrorCode = ErrorCode(0x0);
+    pub(crate) const PROTOCOL_ERROR: ErrorCode = ErrorCode(0x1);
+    pub(crate) const INTERNAL_ERROR: ErrorCode = ErrorCode(0x2);
+    pub(crate) const FLOW_CONTROL_ERROR: ErrorCode = ErrorCode(0x3);
+    pub(crate) const STREAM_CLOSED: ErrorCode = ErrorCode(0x5);
+    pub(crate) const FRAME_SIZE_ERROR: ErrorCode = ErrorCode(0x6);
+    pub(crate) const REFUSED_STREAM: ErrorCode = ErrorCode(0x7);
+    pub(crate) const CANCEL: ErrorCode = ErrorCode(0x8);
+    pub(crate) const COMPRESSION_ERROR: ErrorCode = ErrorCode(0x9);
+    pub(crate) const ENHANCE_YOUR_CALM: ErrorCode = ErrorCode(0xb);
+}
+
+/// Size of the frame header
+const HEADER_LEN: usize = 9;
+/// Frames can't be smaller than this limit
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: usize = 16384;
+/// Largest flow-control window
+pub(crate) const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+/// A received frame
+pub(crate) struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub(crate) fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Strips the padding of `DATA` and `HEADERS` frames
+    pub(crate) fn unpad(&mut self) -> Result<(), ErrorCode> {
+        if !self.has(PADDED) { return Ok(()); }
+        let Some(&pad) = self.payload.first() else { return Err(ErrorCode::FRAME_SIZE_ERROR) };
+        if pad as usize >= self.payload.len() { return Err(ErrorCode::PROTOCOL_ERROR); }
+        self.payload.truncate(self.payload.len() - pad as usize);
+        self.payload.remove(0);
+        Ok(())
+    }
+}
+
+/// Reads 31-bit stream id or window increment
+pub(crate) fn read_u31(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap()) & 0x7fff_ffff
+}
+
+/// Appends a frame to `out`
+pub(crate) fn write(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Appends a header block as `HEADERS` and `CONTINUATION` frames
+pub(crate) fn write_headers(out: &mut Vec<u8>, stream: u32, block: &[u8], end_stream: bool, max_frame_size: usize) {
+    let mut chunks = block.chunks(max_frame_size).peekable();
+    let mut kind = HEADERS;
+    let mut flags = if end_stream { END_STREAM } else { 0 };
+    // empty block still needs a frame
+    if block.is_empty() {
+        write(out, kind, flags | END_HEADERS, stream, &[]);
+    }
+    while let Some(chunk) = chunks.next() {
+        if chunks.peek().is_none() { flags |= END_HEADERS; }
+        write(out, kind, flags, stream, chunk);
+        kind = CONTINUATION;
+        flags = 0;
+    }
+}
+
+pub(crate) fn write_settings(out: &mut Vec<u8>, settings: &[(u16, u32)]) {
+    let mut payload = vec![];
+    for (id, value) in settings {
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend_from_slice(&value.to_be_bytes());
+    }
+    write(out, SETTINGS, 0, 0, &payload);
+}
+
+pub(crate) fn write_rst_stream(out: &mut Vec<u8>, stream: u32, code: ErrorCode) {
+    write(out, RST_STREAM, 0, stream, &code.0.to_be_bytes());
+}
+
+pub(crate) fn write_window_update(out: &mut Vec<u8>, stream: u32, increment: u32) {
+    write(out, WINDOW_UPDATE, 0, stream, &increment.to_be_bytes());
+}
+
+pub(crate) fn write_goaway(out: &mut Vec<u8>, last_stream: u32, code: ErrorCode) {
+    let mut payload = last_stream.to_be_bytes().to_vec();
+    payload.extend_from_slice(&code.0.to_be_bytes());
+    write(out, GOAWAY, 0, 0, &payload);
+}
+
+/// Parses the payload of a `SETTINGS` frame
+pub(crate) fn parse_settings(payload: &[u8]) -> Result<Vec<(u16, u32)>, ErrorCode> {
+    if !payload.len().is_multiple_of(6) { return Err(ErrorCode::FRAME_SIZE_ERROR); }
+    Ok(payload.chunks(6).map(|s| (u16::from_be_bytes([s[0], s[1]]), u32::from_be_bytes([s[2], s[3], s[4], s[5]]))).collect())
+}
+
+/// Reads frames from a buffered stream
+///
+/// Poll-based, so that reading can be interleaved with everything else that happens on the connection
+pub(crate) struct FrameReader {
+    buf: Vec<u8>,
+    /// Size of the frame being read, once the header is complete
+    len: Option<usize>,
+    /// Frames larger than this are a `FRAME_SIZE_ERROR`
+    pub max_frame_size: usize,
+}
+
+/// Read error, either IO or a protocol violation
+pub(crate) enum ReadError {
+    Io(io::Error),
+    Protocol(ErrorCode),
+}
+
+impl FrameReader {
+    pub(crate) fn new() -> FrameReader {
+        FrameReader { buf: vec![], len: None, max_frame_size: DEFAULT_MAX_FRAME_SIZE }
+    }
+
+    /// Reads the next frame, `None` if connection has ended between frames
+    pub(crate) fn poll_frame(&mut self, mut conn: Pin<&mut impl AsyncBufRead>, cx: &mut Context<'_>) -> Poll<Result<Option<Frame>, ReadError>> {
+        loop {
+            let want = HEADER_LEN + self.len.unwrap_or(0);
+            if self.buf.len() == want {
+                if self.len.is_none() {
+                    let len = u32::from_be_bytes([0, self.buf[0], self.buf[1], self.buf[2]]) as usize;
+                    if len > self.max_frame_size { return Poll::Ready(Err(ReadError::Protocol(ErrorCode::FRAME_SIZE_ERROR))); }
+                    self.len = Some(len);
+                    continue;
+                }
+                let frame = Frame {
+                    kind: self.buf[3],
+                    flags: self.buf[4],
+                    stream: read_u31(&self.buf[5..]),
+                    payload: self.buf.split_off(HEADER_LEN),
+                };
+                self.buf.clear();
+                self.len = None;
+                return Poll::Ready(Ok(Some(frame)));
+            }
+
+            let data = ready!(conn.as_mut().poll_fill_buf(cx)).map_err(ReadError::Io)?;
+            if data.is_empty() {
+                if self.buf.is_empty() { return Poll::Ready(Ok(None)); }
+                return Poll::Ready(Err(ReadError::Io(ErrorKind::UnexpectedEof.into())));
+            }
+            let amt = data.len().min(want - self.buf.len());
+            self.buf.extend_from_slice(&data[..amt]);
+            conn.as_mut().consume(amt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_frames() {
+        let mut input = vec![];
+        write(&mut input, PING, ACK, 0, b"12345678");
+        write_headers(&mut input, 3, &[1; 20], true, 16);
+        let mut conn = &input[..];
+        let mut reader = FrameReader::new();
+        let waker = std::task::Waker::noop();
+        let mut cx = Context::from_waker(waker);
+
+        let mut frames = vec![];
+        while let Poll::Ready(Ok(Some(frame))) = reader.poll_frame(Pin::new(&mut conn), &mut cx) {
+            frames.push((frame.kind, frame.flags, frame.stream, frame.payload.len()));
+        }
+        assert_eq!(frames, [(PING, ACK, 0, 8), (HEADERS, END_STREAM, 3, 16), (CONTINUATION, END_HEADERS, 3, 4)]);
+    }
+
+    #[test]
+    fn padding() {
+        let mut frame = Frame { kind: DATA, flags: PADDED, stream: 1, payload: b"\x02abc\0\0".to_vec() };
+        frame.unpad().unwrap();
+        assert_eq!(frame.payload, b"abc");
+        let mut frame = Frame { kind: DATA, flags: PADDED, stream: 1, payload: b"\x03ab".to_vec() };
+        assert_eq!(frame.unpad().err(), Some(ErrorCode::PROTOCOL_ERROR));
+    }
+}
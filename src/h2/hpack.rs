@@ -0,0 +1,319 @@
+//! HPACK header compression (RFC 7541)
+
+use std::collections::VecDeque;
+
+use crate::h2::huffman;
+
+/// Static table, indexed from 1
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Every table entry costs this much in addition to its name and value
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Header field as it was sent, may contain anything
+pub(crate) type Field = (Vec<u8>, Vec<u8>);
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum HpackError {
+    /// Header block can't be decoded, the connection has to be closed
+    Invalid,
+    /// Header list exceeded the limit. Block was decoded anyway and the connection is still usable
+    TooLarge,
+}
+
+fn entry_size(name: &[u8], value: &[u8]) -> usize {
+    name.len() + value.len() + ENTRY_OVERHEAD
+}
+
+/// Decodes an integer with an `n`-bit prefix
+fn decode_int(input: &mut &[u8], n: u8) -> Result<usize, HpackError> {
+    let (&first, rest) = input.split_first().ok_or(HpackError::Invalid)?;
+    *input = rest;
+    let max = (1 << n) - 1;
+    let mut value = (first & max) as usize;
+    if value < max as usize { return Ok(value); }
+
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first().ok_or(HpackError::Invalid)?;
+        *input = rest;
+        // nothing sane needs more than 28 bits
+        if shift > 21 { return Err(HpackError::Invalid); }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 { return Ok(value); }
+    }
+}
+
+fn decode_string(input: &mut &[u8]) -> Result<Vec<u8>, HpackError> {
+    let huffman = input.first().is_some_and(|&b| b & 0x80 != 0);
+    let len = decode_int(input, 7)?;
+    if len > input.len() { return Err(HpackError::Invalid); }
+    let (string, rest) = input.split_at(len);
+    *input = rest;
+    if huffman {
+        huffman::decode(string).ok_or(HpackError::Invalid)
+    } else {
+        Ok(string.to_vec())
+    }
+}
+
+/// Encodes an integer with an `n`-bit prefix, `flags` are put in the bits before the prefix
+fn encode_int(out: &mut Vec<u8>, mut value: usize, n: u8, flags: u8) {
+    let max = (1 << n) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn encode_string(out: &mut Vec<u8>, string: &[u8]) {
+    if huffman::encoded_len(string) < string.len() {
+        encode_int(out, huffman::encoded_len(string), 7, 0x80);
+        out.extend_from_slice(&huffman::encode(string));
+    } else {
+        encode_int(out, string.len(), 7, 0);
+        out.extend_from_slice(string);
+    }
+}
+
+/// Decompresses header blocks, keeps the dynamic table between them
+pub(crate) struct Decoder {
+    /// Newest entries first
+    table: VecDeque<Field>,
+    size: usize,
+    /// Current size limit, changed by the encoder
+    max_size: usize,
+    /// Our `SETTINGS_HEADER_TABLE_SIZE`, encoder can't go above it
+    limit: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new(limit: usize) -> Decoder {
+        Decoder { table: VecDeque::new(), size: 0, max_size: limit, limit }
+    }
+
+    fn get(&self, index: usize) -> Result<Field, HpackError> {
+        match index {
+            0 => Err(HpackError::Invalid),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            }
+            _ => self.table.get(index - 62).cloned().ok_or(HpackError::Invalid),
+        }
+    }
+
+    fn evict(&mut self, max_size: usize) {
+        while self.size > max_size {
+            let (name, value) = self.table.pop_back().unwrap();
+            self.size -= entry_size(&name, &value);
+        }
+    }
+
+    fn insert(&mut self, field: Field) {
+        let size = entry_size(&field.0, &field.1);
+        if size > self.max_size {
+            // too big entry just clears the table
+            self.evict(0);
+            return;
+        }
+        self.evict(self.max_size - size);
+        self.size += size;
+        self.table.push_front(field);
+    }
+
+    /// Decodes a complete header block
+    ///
+    /// Fields are collected while their size (as in `SETTINGS_MAX_HEADER_LIST_SIZE`) is within `max_list_size`
+    pub(crate) fn decode(&mut self, mut input: &[u8], max_list_size: usize) -> Result<Vec<Field>, HpackError> {
+        let mut fields = vec![];
+        let mut list_size = 0;
+        let mut too_large = false;
+        let mut first = true;
+        while let Some(&byte) = input.first() {
+            let field = if byte & 0x80 != 0 {
+                // indexed
+                let index = decode_int(&mut input, 7)?;
+                self.get(index)?
+            } else if byte & 0xe0 == 0x20 {
+                // dynamic table size update, only allowed at the start of a block
+                if !first { return Err(HpackError::Invalid); }
+                let size = decode_int(&mut input, 5)?;
+                if size > self.limit { return Err(HpackError::Invalid); }
+                self.max_size = size;
+                self.evict(size);
+                continue;
+            } else {
+                // literal, with incremental indexing (6-bit prefix) or without it (4-bit)
+                let indexing = byte & 0xc0 == 0x40;
+                let index = decode_int(&mut input, if indexing { 6 } else { 4 })?;
+                let name = if index == 0 { decode_string(&mut input)? } else { self.get(index)?.0 };
+                let value = decode_string(&mut input)?;
+                if indexing {
+                    self.insert((name.clone(), value.clone()));
+                }
+                (name, value)
+            };
+            first = false;
+
+            list_size += entry_size(&field.0, &field.1);
+            if list_size > max_list_size {
+                too_large = true;
+                fields.clear();
+            }
+            if !too_large {
+                fields.push(field);
+            }
+        }
+        if too_large { return Err(HpackError::TooLarge); }
+        Ok(fields)
+    }
+}
+
+/// Compresses header blocks
+///
+/// Dynamic table is never used, so encoder does not need to track any state
+pub(crate) fn encode(out: &mut Vec<u8>, name: &str, value: &str) {
+    let exact = STATIC_TABLE.iter().position(|&(n, v)| n == name && v == value);
+    if let Some(index) = exact {
+        encode_int(out, index + 1, 7, 0x80);
+        return;
+    }
+    // literal without indexing
+    match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+        Some(index) => encode_int(out, index + 1, 4, 0),
+        None => {
+            out.push(0);
+            encode_string(out, name.as_bytes());
+        }
+    }
+    encode_string(out, value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn fields(fields: &[Field]) -> Vec<(&str, &str)> {
+        fields.iter().map(|(n, v)| (str::from_utf8(n).unwrap(), str::from_utf8(v).unwrap())).collect()
+    }
+
+    /// RFC 7541, appendix C.3 and C.4: same requests without and with Huffman coding
+    #[test]
+    fn rfc_requests() {
+        for blocks in [
+            ["828684410f7777772e6578616d706c652e636f6d", "828684be58086e6f2d6361636865", "828785bf400a637573746f6d2d6b65790c637573746f6d2d76616c7565"],
+            ["828684418cf1e3c2e5f23a6ba0ab90f4ff", "828684be5886a8eb10649cbf", "828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf"],
+        ] {
+            let mut decoder = Decoder::new(4096);
+            let first = decoder.decode(&hex(blocks[0]), usize::MAX).unwrap();
+            assert_eq!(fields(&first), [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")]);
+            let second = decoder.decode(&hex(blocks[1]), usize::MAX).unwrap();
+            assert_eq!(fields(&second), [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"), ("cache-control", "no-cache")]);
+            let third = decoder.decode(&hex(blocks[2]), usize::MAX).unwrap();
+            assert_eq!(fields(&third), [(":method", "GET"), (":scheme", "https"), (":path", "/index.html"), (":authority", "www.example.com"), ("custom-key", "custom-value")]);
+            assert_eq!(decoder.size, 164);
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let input = [(":status", "200"), (":status", "418"), ("content-type", "text/html"), ("x-custom", "value"), ("x-binary", "\x7f\x00")];
+        let mut out = vec![];
+        for (name, value) in input {
+            encode(&mut out, name, value);
+        }
+        let decoded = Decoder::new(4096).decode(&out, usize::MAX).unwrap();
+        assert_eq!(fields(&decoded), input);
+    }
+
+    #[test]
+    fn invalid() {
+        for block in ["80", "be", "0f", "4005", "00027878", "3fe21f", "8220", "41ffffffffffff"] {
+            assert_eq!(Decoder::new(4096).decode(&hex(block), usize::MAX), Err(HpackError::Invalid), "{block}");
+        }
+    }
+
+    #[test]
+    fn too_large() {
+        let mut decoder = Decoder::new(4096);
+        let block = hex("828684410f7777772e6578616d706c652e636f6d");
+        assert_eq!(decoder.decode(&block, 100), Err(HpackError::TooLarge));
+        // table has the entry anyway
+        assert_eq!(decoder.table.len(), 1);
+    }
+}
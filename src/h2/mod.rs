@@ -0,0 +1,321 @@
+//! HTTP/2 connection handler (RFC 9113)
+//!
+//! Only cleartext HTTP/2 is handled here, it's started either with prior knowledge
+//! (the client sends the preface right away) or with `Upgrade: h2c`
+
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, OnceLock};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::core::connection::{HttpConnection, Timeout};
+use crate::h1;
+use crate::reqres::{HttpRequest, HttpResponse, HttpHeader, HttpVersion, HttpMethod, HttpBody, StatusCode, RequestTarget};
+use crate::server::HttpServer;
+
+mod frame;
+mod hpack;
+mod huffman;
+mod stream;
+mod conn;
+
+use conn::Connection;
+use frame::ErrorCode;
+use hpack::Field;
+use stream::StreamIo;
+
+/// Client connection preface, the start of it is parsed as an HTTP/1.1 request line
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Headers that only make sense in HTTP/1.1, they make a request malformed
+const CONNECTION_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+/// Is it the start of the preface? (`PRI * HTTP/2.0` without headers)
+pub(crate) fn is_prior_knowledge(req: &HttpRequest) -> bool {
+    req.method == HttpMethod::Other("PRI".to_string()) && req.target == RequestTarget::Asterisk
+        && req.version.is(2, 0) && req.headers.is_empty()
+}
+
+/// Decodes base64url without padding, used in `HTTP2-Settings`
+fn decode_base64url(input: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in input.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6 | value as u32) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Checks if the request asks for `Upgrade: h2c`, and returns the settings from `HTTP2-Settings`
+///
+/// Requests with a body are served over HTTP/1.1, because the body would have to be read before switching
+pub(crate) fn upgrade_settings(req: &HttpRequest) -> Option<Vec<(u16, u32)>> {
+    if !req.version.is(1, 1) || req.len != Some(0) { return None; }
+    if !req.get_headers("Upgrade").any(|u| h1::has_token(u, "h2c")) { return None; }
+    let connection = |token| req.get_headers("Connection").any(|c| h1::has_token(c, token));
+    if !connection("upgrade") || !connection("http2-settings") { return None; }
+
+    let mut settings = req.get_headers("HTTP2-Settings");
+    let (Some(settings), None) = (settings.next(), settings.next()) else { return None };
+    frame::parse_settings(&decode_base64url(settings.trim())?).ok()
+}
+
+/// Reads the rest of client preface
+async fn read_preface(server: &HttpServer, conn: &mut Timeout<impl HttpConnection>, expected: &[u8]) -> io::Result<()> {
+    let mut preface = vec![0; expected.len()];
+    let read = conn.read_exact(&mut preface);
+    match server.header_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, read).await.map_err(|_| io::Error::from(ErrorKind::TimedOut))??,
+        None => read.await?,
+    };
+    if preface != expected {
+        return Err(io::Error::new(ErrorKind::InvalidData, "invalid HTTP/2 preface"));
+    }
+    Ok(())
+}
+
+/// Serves a connection that has started with `PRI * HTTP/2.0`
+pub(crate) async fn serve_prior_knowledge(server: &HttpServer, conn: &mut Timeout<impl HttpConnection>) -> io::Result<()> {
+    // `PRI * HTTP/2.0\r\n\r\n` was already read as an HTTP/1.1 request
+    read_preface(server, conn, b"SM\r\n\r\n").await?;
+    Connection::new(server, conn).await?;
+    conn.shutdown().await
+}
+
+/// Switches to HTTP/2 after an `Upgrade: h2c` request, its response is sent as stream 1
+pub(crate) async fn serve_upgrade(server: &HttpServer, conn: &mut Timeout<impl HttpConnection>, mut req: HttpRequest, settings: Vec<(u16, u32)>) -> io::Result<()> {
+    conn.write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n").await?;
+    read_preface(server, conn, PREFACE).await?;
+
+    // Upgrade is done, these headers are not for the service
+    h1::strip_connection_headers(&mut req.headers);
+    req.headers.retain(|h| !h.name.eq_ignore_ascii_case("Connection") && !h.name.eq_ignore_ascii_case("Upgrade"));
+
+    let mut connection = Connection::new(server, conn);
+    // settings from `HTTP2-Settings` don't need an ACK
+    match connection.apply_settings(&settings) {
+        Ok(()) => connection.open(1, Ok(req), true),
+        Err(code) => connection.fail(code),
+    }
+    connection.await?;
+    conn.shutdown().await
+}
+
+/// Why a stream can't be served
+pub(crate) enum Rejected {
+    /// Stream is reset with `PROTOCOL_ERROR`
+    Malformed,
+    /// Error response is sent instead of running the service
+    Status(StatusCode),
+}
+
+/// Field values can't have line breaks and NUL, or whitespace around them
+fn is_valid_value(value: &str) -> bool {
+    !value.contains(['\r', '\n', '\0']) && !value.starts_with([' ', '\t']) && !value.ends_with([' ', '\t'])
+}
+
+/// Converts a field into a regular header
+fn make_header((name, value): Field) -> Option<HttpHeader> {
+    let name = String::from_utf8(name).ok()?;
+    let value = String::from_utf8(value).ok()?;
+    // names have to be lowercase in HTTP/2
+    if !h1::is_token(&name) || name.bytes().any(|c| c.is_ascii_uppercase()) { return None; }
+    if !is_valid_value(&value) || CONNECTION_HEADERS.contains(&name.as_str()) { return None; }
+    if name == "te" && value != "trailers" { return None; }
+    Some(HttpHeader { name, value })
+}
+
+/// Validates trailer fields
+pub(crate) fn make_trailers(fields: Vec<Field>) -> Option<Vec<HttpHeader>> {
+    fields.into_iter().map(make_header).collect()
+}
+
+/// Builds a request from its header block
+pub(crate) fn make_request(fields: Vec<Field>, end_stream: bool, server: &HttpServer) -> Result<HttpRequest, Rejected> {
+    let mut method = None;
+    let mut scheme = None;
+    let mut authority = None;
+    let mut path = None;
+    let mut headers = vec![];
+    let mut cookies = vec![];
+    for (name, value) in fields {
+        if let Some(pseudo) = name.strip_prefix(b":") {
+            // pseudo-headers come first, and only once
+            if !headers.is_empty() || !cookies.is_empty() { return Err(Rejected::Malformed); }
+            let slot = match pseudo {
+                b"method" => &mut method,
+                b"scheme" => &mut scheme,
+                b"authority" => &mut authority,
+                b"path" => &mut path,
+                _ => return Err(Rejected::Malformed),
+            };
+            let value = String::from_utf8(value).map_err(|_| Rejected::Malformed)?;
+            if !is_valid_value(&value) || slot.replace(value).is_some() { return Err(Rejected::Malformed); }
+            continue;
+        }
+        let header = make_header((name, value)).ok_or(Rejected::Malformed)?;
+        // cookie may be split into several fields, they are joined back
+        if header.name == "cookie" {
+            cookies.push(header.value);
+        } else {
+            headers.push(header);
+        }
+    }
+    if headers.len() > server.max_header_count {
+        return Err(Rejected::Status(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE));
+    }
+
+    let method = HttpMethod::new(&method.ok_or(Rejected::Malformed)?);
+    let (target, route, raw_target) = if method == HttpMethod::Connect {
+        let (None, None, Some(authority)) = (&scheme, &path, &authority) else { return Err(Rejected::Malformed) };
+        (RequestTarget::Authority, "/".to_string(), authority.clone())
+    } else {
+        let (Some(_), Some(path)) = (scheme, path) else { return Err(Rejected::Malformed) };
+        if path == "*" && method == HttpMethod::Options {
+            (RequestTarget::Asterisk, "*".to_string(), path)
+        } else if path.starts_with('/') {
+            (RequestTarget::Origin, path.clone(), path)
+        } else {
+            return Err(Rejected::Malformed);
+        }
+    };
+    if route.len() > server.max_uri_len {
+        return Err(Rejected::Status(StatusCode::URI_TOO_LONG));
+    }
+
+    // `:authority` takes the role of Host
+    if let Some(authority) = authority {
+        headers.retain(|h| h.name != "host");
+        headers.insert(0, HttpHeader { name: "host".to_string(), value: authority });
+    }
+    if !cookies.is_empty() {
+        headers.push(HttpHeader { name: "cookie".to_string(), value: cookies.join("; ") });
+    }
+
+    let mut len = None;
+    for value in headers.iter().filter(|h| h.name == "content-length").flat_map(|h| h.value.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|c| c.is_ascii_digit()) { return Err(Rejected::Malformed); }
+        let value = value.parse().map_err(|_| Rejected::Malformed)?;
+        if len.is_some_and(|len| len != value) { return Err(Rejected::Malformed); }
+        len = Some(value);
+    }
+    if end_stream {
+        if len.is_some_and(|len| len != 0) { return Err(Rejected::Malformed); }
+        len = Some(0);
+    }
+
+    let trailers = Arc::new(OnceLock::new());
+    if end_stream {
+        let _ = trailers.set(vec![]);
+    }
+    Ok(HttpRequest {
+        method,
+        route,
+        target,
+        raw_target,
+        version: HttpVersion { major: 2, minor: 0 },
+        headers,
+        len,
+        addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        port: 0,
+        scheme: "http".to_string(),
+        host: None,
+        id: String::new(),
+        trailers,
+    })
+}
+
+/// Runs the service of a stream and sends the response
+///
+/// `request_number` is the number of this stream on the connection, for the logger
+pub(crate) async fn run_stream(server: &HttpServer, req: Result<HttpRequest, StatusCode>, mut io: StreamIo, request_number: usize) {
+    let result = match req {
+        Ok(req) => {
+            let Some(mut ex) = server.respond(&req, &mut io).await else {
+                return io.reset(ErrorCode::INTERNAL_ERROR);
+            };
+            let result = send(&req, &mut ex.res, &mut io).await;
+            server.log(&req, &ex, request_number, io.written, result.is_err());
+            result
+        }
+        Err(code) => send(&HttpRequest::default(), &mut server.plain_error(code), &mut io).await,
+    };
+    if result.is_err() {
+        io.reset(ErrorCode::INTERNAL_ERROR);
+    }
+}
+
+fn lowercase_fields(headers: &[HttpHeader]) -> Vec<(String, String)> {
+    headers.iter()
+        .map(|h| (h.name.to_ascii_lowercase(), h.value.clone()))
+        .filter(|(name, _)| !CONNECTION_HEADERS.contains(&name.as_str()))
+        .collect()
+}
+
+/// Sends the response on a stream, body is taken from it
+async fn send(req: &HttpRequest, res: &mut HttpResponse, io: &mut StreamIo) -> io::Result<()> {
+    let body = std::mem::replace(&mut res.body, HttpBody::Bytes(vec![]));
+    let mut fields = vec![(":status".to_string(), res.code.0.to_string())];
+    fields.extend(lowercase_fields(&res.headers));
+    if !res.content_type.is_empty() {
+        fields.push(("content-type".to_string(), res.content_type.clone()));
+    }
+    match &body {
+        HttpBody::Bytes(bytes) => fields.push(("content-length".to_string(), bytes.len().to_string())),
+        HttpBody::File { len, .. } => fields.push(("content-length".to_string(), len.to_string())),
+        HttpBody::Stream(_) | HttpBody::Upgrade(_) => {}
+    }
+
+    let mut trailers = std::mem::take(&mut res.trailers);
+    let empty = matches!(&body, HttpBody::Bytes(bytes) if bytes.is_empty()) && trailers.is_empty();
+    // Don't send body on head requests
+    if req.method == HttpMethod::Head || empty {
+        return io.send_headers(&fields, true);
+    }
+    io.send_headers(&fields, false)?;
+
+    match body {
+        HttpBody::Bytes(bytes) => {
+            io.write_all(&bytes).await?;
+        }
+        HttpBody::File { mut file, len } => {
+            tokio::io::copy(&mut (&mut file).take(len), io).await?;
+        }
+        HttpBody::Stream(mut stream) => {
+            while let Some(chunk) = stream.next_raw().await? {
+                io.write_all(&chunk).await?;
+            }
+            trailers.extend(stream.trailers_raw());
+        }
+        // The stream works like a connection, this is how SSE works over HTTP/2
+        HttpBody::Upgrade(mut handler) => {
+            handler.upgrade_raw(io).await?;
+        }
+    }
+
+    // HTTP/2 can always send trailers
+    if trailers.is_empty() {
+        io.shutdown().await
+    } else {
+        io.send_headers(&lowercase_fields(&trailers), true)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,304 @@
+//! State shared between the connection and its streams
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+
+use tokio::io::{AsyncRead, AsyncBufRead, AsyncWrite, ReadBuf};
+
+use crate::core::connection::HttpConnection;
+use crate::h2::frame::{self, ErrorCode};
+use crate::h2::hpack;
+use crate::reqres::HttpHeader;
+
+/// Streams stop writing, and the connection stops reading, when this much output is waiting
+pub(crate) const OUT_LIMIT: usize = 65536;
+
+/// Receive window of every stream, advertised in `SETTINGS_INITIAL_WINDOW_SIZE`
+pub(crate) const STREAM_WINDOW: i64 = 256 * 1024;
+/// Receive window of the connection
+pub(crate) const CONN_WINDOW: i64 = 1024 * 1024;
+
+pub(crate) struct Stream {
+    /// Received body chunks
+    pub recv: VecDeque<Vec<u8>>,
+    /// `END_STREAM` was received
+    pub recv_closed: bool,
+    /// How much more the client may send
+    pub recv_window: i64,
+    /// Body bytes received so far, and the expected amount from `content-length`
+    pub received: u64,
+    pub len: Option<u64>,
+    pub trailers: Arc<OnceLock<Vec<HttpHeader>>>,
+    pub reader: Option<Waker>,
+    /// How much more we may send
+    pub send_window: i64,
+    /// `END_STREAM` was sent
+    pub send_closed: bool,
+    /// Stream was reset by either side
+    pub reset: bool,
+}
+
+impl Stream {
+    pub(crate) fn new(recv_window: i64, send_window: i64, len: Option<u64>, trailers: Arc<OnceLock<Vec<HttpHeader>>>) -> Stream {
+        Stream {
+            recv: VecDeque::new(),
+            recv_closed: false,
+            recv_window,
+            received: 0,
+            len,
+            trailers,
+            reader: None,
+            send_window,
+            send_closed: false,
+            reset: false,
+        }
+    }
+
+    /// Marks the end of the request body
+    pub(crate) fn close_recv(&mut self) {
+        self.recv_closed = true;
+        let _ = self.trailers.set(vec![]);
+        self.wake_reader();
+    }
+
+    pub(crate) fn wake_reader(&mut self) {
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub(crate) struct Shared {
+    pub streams: HashMap<u32, Stream>,
+    /// Frames waiting to be written
+    pub out: Vec<u8>,
+    /// How much more we may send on the connection
+    pub send_window: i64,
+    /// Client's `SETTINGS_INITIAL_WINDOW_SIZE`
+    pub initial_window: i64,
+    /// Client's `SETTINGS_MAX_FRAME_SIZE`
+    pub max_frame_size: usize,
+    /// How much more the client may send on the connection
+    pub recv_window: i64,
+    /// Received body bytes that were read by services, but not returned to the client's window
+    pub consumed: i64,
+    /// Streams waiting for a window or for `out` to drain
+    pub blocked: Vec<Waker>,
+    /// Connection is going down, streams can't send anything
+    pub closed: bool,
+    /// Control frames (ACKs and `RST_STREAM`) queued since the output was last written out
+    pub control: usize,
+}
+
+impl Shared {
+    pub(crate) fn new() -> Shared {
+        Shared {
+            streams: HashMap::new(),
+            out: vec![],
+            send_window: 65535,
+            initial_window: 65535,
+            max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE,
+            recv_window: CONN_WINDOW,
+            consumed: 0,
+            blocked: vec![],
+            closed: false,
+            control: 0,
+        }
+    }
+
+    pub(crate) fn wake_blocked(&mut self) {
+        for waker in self.blocked.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Returns received bytes to the client's windows once a good amount of them was read
+    pub(crate) fn release(&mut self, id: u32, amt: usize) {
+        self.consumed += amt as i64;
+        if self.consumed >= CONN_WINDOW / 2 {
+            frame::write_window_update(&mut self.out, 0, self.consumed as u32);
+            self.recv_window += self.consumed;
+            self.consumed = 0;
+        }
+        let Some(stream) = self.streams.get_mut(&id) else { return };
+        if stream.recv_closed || stream.reset { return; }
+        // window is only increased when half of it was used up
+        let used = STREAM_WINDOW - stream.recv_window - stream.recv.iter().map(Vec::len).sum::<usize>() as i64;
+        if used >= STREAM_WINDOW / 2 {
+            stream.recv_window += used;
+            frame::write_window_update(&mut self.out, id, used as u32);
+        }
+    }
+
+    /// Resets the stream on both sides
+    pub(crate) fn reset(&mut self, id: u32, code: ErrorCode) {
+        frame::write_rst_stream(&mut self.out, id, code);
+        self.control += 1;
+        self.abort(id);
+    }
+
+    /// Stops the stream after it was reset, its service will be dropped
+    pub(crate) fn abort(&mut self, id: u32) {
+        let Some(stream) = self.streams.get_mut(&id) else { return };
+        stream.reset = true;
+        stream.wake_reader();
+        let buffered: usize = stream.recv.drain(..).map(|chunk| chunk.len()).sum();
+        self.release(id, buffered);
+        self.wake_blocked();
+    }
+}
+
+/// One stream, as seen by the service
+///
+/// Reads the request body, and writes the response body as `DATA` frames.
+/// It's only polled from the connection task, which writes the frames out
+pub(crate) struct StreamIo {
+    pub id: u32,
+    pub shared: Arc<Mutex<Shared>>,
+    buf: Vec<u8>,
+    pos: usize,
+    peer: Option<SocketAddr>,
+    secure: bool,
+    /// Bytes of the response body, for the logger
+    pub written: u64,
+}
+
+impl StreamIo {
+    pub(crate) fn new(id: u32, shared: Arc<Mutex<Shared>>, peer: Option<SocketAddr>, secure: bool) -> StreamIo {
+        StreamIo { id, shared, buf: vec![], pos: 0, peer, secure, written: 0 }
+    }
+
+    /// Sends a header block, either response headers or trailers
+    pub(crate) fn send_headers(&mut self, fields: &[(String, String)], end_stream: bool) -> io::Result<()> {
+        let mut shared = self.shared.lock().unwrap();
+        let closed = shared.closed;
+        let stream = shared.streams.get_mut(&self.id).filter(|s| !s.reset && !closed);
+        let Some(stream) = stream else { return Err(ErrorKind::ConnectionReset.into()) };
+        stream.send_closed |= end_stream;
+
+        let mut block = vec![];
+        for (name, value) in fields {
+            hpack::encode(&mut block, name, value);
+        }
+        let max_frame_size = shared.max_frame_size;
+        frame::write_headers(&mut shared.out, self.id, &block, end_stream, max_frame_size);
+        Ok(())
+    }
+
+    pub(crate) fn reset(&mut self, code: ErrorCode) {
+        let mut shared = self.shared.lock().unwrap();
+        let reset = shared.streams.get(&self.id).is_none_or(|s| s.reset);
+        if !reset {
+            shared.reset(self.id, code);
+        }
+    }
+}
+
+impl Drop for StreamIo {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        let Some(stream) = shared.streams.get(&self.id) else { return };
+        if !stream.reset && !shared.closed {
+            if !stream.send_closed {
+                // response was abandoned
+                shared.reset(self.id, ErrorCode::CANCEL);
+            } else if !stream.recv_closed {
+                // response is complete, the rest of request body is not needed
+                shared.reset(self.id, ErrorCode::NO_ERROR);
+            }
+        }
+        shared.streams.remove(&self.id);
+    }
+}
+
+impl AsyncRead for StreamIo {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let data = std::task::ready!(self.as_mut().poll_fill_buf(cx))?;
+        let amt = data.len().min(buf.remaining());
+        buf.put_slice(&data[..amt]);
+        self.consume(amt);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncBufRead for StreamIo {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.pos == this.buf.len() {
+            let mut shared = this.shared.lock().unwrap();
+            let Some(stream) = shared.streams.get_mut(&this.id) else { return Poll::Ready(Err(ErrorKind::ConnectionReset.into())) };
+            if stream.reset { return Poll::Ready(Err(ErrorKind::ConnectionReset.into())); }
+            match stream.recv.pop_front() {
+                Some(chunk) => {
+                    this.buf = chunk;
+                    this.pos = 0;
+                    shared.release(this.id, this.buf.len());
+                }
+                None if stream.recv_closed => return Poll::Ready(Ok(&[])),
+                None => {
+                    stream.reader = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+        Poll::Ready(Ok(&this.buf[this.pos..]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().pos += amt;
+    }
+}
+
+impl AsyncWrite for StreamIo {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut shared = this.shared.lock().unwrap();
+        let shared = &mut *shared;
+        let stream = shared.streams.get_mut(&this.id).filter(|s| !s.reset && !s.send_closed && !shared.closed);
+        let Some(stream) = stream else { return Poll::Ready(Err(ErrorKind::ConnectionReset.into())) };
+        if buf.is_empty() { return Poll::Ready(Ok(0)); }
+
+        let window = stream.send_window.min(shared.send_window);
+        if window <= 0 || shared.out.len() >= OUT_LIMIT {
+            shared.blocked.push(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let amt = buf.len().min(window as usize).min(shared.max_frame_size);
+        stream.send_window -= amt as i64;
+        shared.send_window -= amt as i64;
+        frame::write(&mut shared.out, frame::DATA, 0, this.id, &buf[..amt]);
+        this.written += amt as u64;
+        Poll::Ready(Ok(amt))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // connection writes everything out as soon as possible
+        Poll::Ready(Ok(()))
+    }
+
+    /// Ends the stream
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut shared = self.shared.lock().unwrap();
+        let Some(stream) = shared.streams.get_mut(&self.id) else { return Poll::Ready(Err(ErrorKind::ConnectionReset.into())) };
+        if stream.reset { return Poll::Ready(Err(ErrorKind::ConnectionReset.into())); }
+        if !stream.send_closed {
+            stream.send_closed = true;
+            frame::write(&mut shared.out, frame::DATA, frame::END_STREAM, self.id, &[]);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl HttpConnection for StreamIo {
+    fn getpeername(&self) -> io::Result<SocketAddr> {
+        self.peer.ok_or_else(|| ErrorKind::NotConnected.into())
+    }
+
+    fn is_secure(&self) -> bool {
+        self.secure
+    }
+}
@@ -0,0 +1,272 @@
+//! Whole connections, driven by a minimal client over an in-memory pipe
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+
+use super::*;
+use crate::core::{HttpResult, HttpRead, HttpService};
+use crate::reqres::res;
+use crate::server::Shutdown;
+use frame::{Frame, FrameReader};
+use hpack::Decoder;
+
+impl HttpConnection for BufReader<DuplexStream> {
+    fn getpeername(&self) -> io::Result<SocketAddr> {
+        Err(ErrorKind::NotConnected.into())
+    }
+
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
+
+/// Responds with the request body and the route
+struct Echo;
+
+impl HttpService for Echo {
+    async fn request(&self, route: &str, _req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        let mut data = vec![];
+        body.read_to_end(&mut data).await?;
+        let mut res = res::bytes(data);
+        res.add_header("X-Route", route);
+        Ok(res)
+    }
+
+    fn filter(&self, _route: &str, _req: &HttpRequest) -> HttpResult<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default, Debug)]
+struct Response {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    reset: Option<ErrorCode>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+struct Client {
+    conn: BufReader<DuplexStream>,
+    shutdown: Shutdown,
+    reader: FrameReader,
+    decoder: Decoder,
+}
+
+impl Client {
+    /// Starts the server on the other end of the pipe, nothing is sent yet
+    fn new() -> Client {
+        Client::with_pipe(1 << 20)
+    }
+
+    /// Starts the server, with at most `size` bytes in flight each way
+    fn with_pipe(size: usize) -> Client {
+        let mut http = HttpServer::new();
+        http.service(Echo);
+        Client::with_server(http, size)
+    }
+
+    fn with_server(http: HttpServer, size: usize) -> Client {
+        let (client, server) = tokio::io::duplex(size);
+        let shutdown = http.shutdown.clone();
+        let http = Arc::new(http);
+        tokio::spawn(async move { http.handle_connection(BufReader::new(server), None).await });
+        Client { conn: BufReader::new(client), shutdown, reader: FrameReader::new(), decoder: Decoder::new(4096) }
+    }
+
+    /// Connects with prior knowledge
+    async fn connect() -> Client {
+        Client::new().handshake().await
+    }
+
+    /// Sends the preface and settings
+    async fn handshake(mut self) -> Client {
+        self.write(PREFACE).await;
+        self.send(frame::SETTINGS, 0, 0, &[]).await;
+        self
+    }
+
+    async fn write(&mut self, data: &[u8]) {
+        self.conn.write_all(data).await.unwrap();
+    }
+
+    async fn send(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+        let mut out = vec![];
+        frame::write(&mut out, kind, flags, stream, payload);
+        self.write(&out).await;
+    }
+
+    async fn request(&mut self, stream: u32, fields: &[(&str, &str)], end_stream: bool) {
+        let mut block = vec![];
+        for (name, value) in fields {
+            hpack::encode(&mut block, name, value);
+        }
+        let mut out = vec![];
+        frame::write_headers(&mut out, stream, &block, end_stream, frame::DEFAULT_MAX_FRAME_SIZE);
+        self.write(&out).await;
+    }
+
+    /// Next frame, `None` once the server has closed the connection
+    async fn frame(&mut self) -> Option<Frame> {
+        std::future::poll_fn(|cx| self.reader.poll_frame(Pin::new(&mut self.conn), cx)).await.ok().flatten()
+    }
+
+    /// Reads frames until `n` streams have ended
+    async fn responses(&mut self, n: usize) -> HashMap<u32, Response> {
+        let mut responses = HashMap::<u32, Response>::new();
+        let mut ended = 0;
+        while ended < n {
+            let frame = self.frame().await.expect("connection closed");
+            let res = responses.entry(frame.stream).or_default();
+            match frame.kind {
+                frame::SETTINGS if !frame.has(frame::ACK) => self.send(frame::SETTINGS, frame::ACK, 0, &[]).await,
+                frame::HEADERS => {
+                    let fields = self.decoder.decode(&frame.payload, usize::MAX).unwrap();
+                    let fields = fields.into_iter().map(|(n, v)| (String::from_utf8(n).unwrap(), String::from_utf8(v).unwrap()));
+                    res.headers.extend(fields);
+                }
+                frame::DATA => res.body.extend_from_slice(&frame.payload),
+                frame::RST_STREAM => {
+                    res.reset = Some(ErrorCode(u32::from_be_bytes(frame.payload[..4].try_into().unwrap())));
+                    ended += 1;
+                }
+                _ => {}
+            }
+            if matches!(frame.kind, frame::HEADERS | frame::DATA) && frame.has(frame::END_STREAM) {
+                ended += 1;
+            }
+        }
+        responses.retain(|&id, _| id != 0);
+        responses
+    }
+
+    /// Reads frames until `GOAWAY`, returns its error code
+    async fn goaway(&mut self) -> ErrorCode {
+        loop {
+            let frame = self.frame().await.expect("connection closed");
+            if frame.kind == frame::GOAWAY {
+                return ErrorCode(u32::from_be_bytes(frame.payload[4..8].try_into().unwrap()));
+            }
+        }
+    }
+}
+
+fn run(test: impl Future<Output = ()>) {
+    tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap().block_on(test)
+}
+
+const GET: [(&str, &str); 4] = [(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "a")];
+
+#[test]
+fn requests() {
+    run(async {
+        let mut client = Client::connect().await;
+        // body of stream 1 is sent after stream 3 is complete
+        let post = [(":method", "POST"), (":scheme", "http"), (":path", "/post"), (":authority", "a"), ("content-length", "5")];
+        client.request(1, &post, false).await;
+        client.request(3, &GET, true).await;
+        let res = client.responses(1).await;
+        assert_eq!(res[&3].header(":status"), Some("200"));
+        assert_eq!(res[&3].header("x-route"), Some("/"));
+        assert_eq!(res[&3].header("content-length"), Some("0"));
+
+        client.send(frame::DATA, 0, 1, b"he").await;
+        client.send(frame::DATA, frame::END_STREAM, 1, b"llo").await;
+        let res = client.responses(1).await;
+        assert_eq!(res[&1].header(":status"), Some("200"));
+        assert_eq!(res[&1].header("x-route"), Some("/post"));
+        assert_eq!(res[&1].body, b"hello");
+    });
+}
+
+#[test]
+fn malformed() {
+    run(async {
+        let mut client = Client::connect().await;
+        // no :path
+        client.request(1, &GET[..2], true).await;
+        client.request(3, &[(":method", "GET"), (":scheme", "http"), (":path", "/"), ("connection", "close")], true).await;
+        client.request(5, &GET, true).await;
+        let res = client.responses(3).await;
+        assert_eq!(res[&1].reset, Some(ErrorCode::PROTOCOL_ERROR));
+        assert_eq!(res[&3].reset, Some(ErrorCode::PROTOCOL_ERROR));
+        assert_eq!(res[&5].header(":status"), Some("200"));
+
+        // DATA on stream 0 ends the connection
+        client.send(frame::DATA, 0, 0, b"x").await;
+        assert_eq!(client.goaway().await, ErrorCode::PROTOCOL_ERROR);
+        while client.frame().await.is_some() {}
+    });
+}
+
+#[test]
+fn shutdown() {
+    run(async {
+        let mut client = Client::connect().await;
+        client.request(1, &[(":method", "POST"), (":scheme", "http"), (":path", "/"), (":authority", "a")], false).await;
+        client.shutdown.trigger();
+        assert_eq!(client.goaway().await, ErrorCode::NO_ERROR);
+
+        // running stream is finished, then the connection is closed
+        client.send(frame::DATA, frame::END_STREAM, 1, b"done").await;
+        let res = client.responses(1).await;
+        assert_eq!(res[&1].body, b"done");
+        assert!(client.frame().await.is_none());
+    });
+}
+
+#[test]
+fn upgrade() {
+    run(async {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let checks2 = Arc::clone(&checks);
+        let mut http = HttpServer::new();
+        http.service(Echo).trust_request_id(move |_req, _id| {
+            checks2.fetch_add(1, Ordering::Relaxed);
+            true
+        });
+        let mut client = Client::with_server(http, 1 << 20);
+        client.write(b"GET /up HTTP/1.1\r\nHost: a\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABk\r\nX-Request-Id: abc\r\n\r\n").await;
+        let mut status = String::new();
+        client.conn.read_line(&mut status).await.unwrap();
+        assert_eq!(status, "HTTP/1.1 101 Switching Protocols\r\n");
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            client.conn.read_line(&mut line).await.unwrap();
+        }
+
+        client.write(PREFACE).await;
+        client.send(frame::SETTINGS, 0, 0, &[]).await;
+        let res = client.responses(1).await;
+        assert_eq!(res[&1].header(":status"), Some("200"));
+        assert_eq!(res[&1].header("x-route"), Some("/up"));
+        // resolved once, on the HTTP/1.1 request
+        assert_eq!(res[&1].header("x-request-id"), Some("abc"));
+        assert_eq!(checks.load(Ordering::Relaxed), 1);
+    });
+}
+
+#[test]
+fn ping_flood() {
+    run(async {
+        let mut client = Client::with_pipe(4096).handshake().await;
+        // ACKs are never read, so they pile up on the server
+        let mut pings = vec![];
+        for i in 0..5000u64 {
+            frame::write(&mut pings, frame::PING, 0, 0, &i.to_be_bytes());
+        }
+        // server stops reading at some point
+        let _ = tokio::time::timeout(Duration::from_millis(200), client.write(&pings)).await;
+        assert_eq!(client.goaway().await, ErrorCode::ENHANCE_YOUR_CALM);
+    });
+}
@@ -1,6 +1,8 @@
 //! DrakoHTTP: the best web framework
 
 pub(crate) mod h1;
+pub(crate) mod h2;
+pub(crate) mod proxy;
 
 pub mod reqres;
 pub mod core;
@@ -8,5 +10,16 @@ pub mod services;
 pub mod prelude;
 pub mod server;
 pub mod util;
+pub mod forwarded;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(unix)]
+pub mod unix;
+#[cfg(unix)]
+pub mod systemd;
 
-pub use server::{tokio_rt, serve_tcp};
+pub use server::{tokio_rt, serve_tcp, HttpListener, ListenerGroup};
+#[cfg(feature = "tls")]
+pub use tls::serve_tls;
+#[cfg(unix)]
+pub use unix::serve_unix;
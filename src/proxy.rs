@@ -0,0 +1,166 @@
+//! PROXY protocol, versions 1 and 2
+//!
+//! <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>
+
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Both versions can be told apart by the first 6 bytes
+const V1_START: &[u8; 6] = b"PROXY ";
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest v1 header, with `\r\n`
+const V1_MAX_LEN: usize = 107;
+
+fn invalid() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "invalid PROXY protocol header")
+}
+
+/// Parses the v1 line after `PROXY `, without `\r\n`
+fn parse_v1(line: &str) -> Option<Option<SocketAddr>> {
+    let mut parts = line.split(' ');
+    let proto = parts.next()?;
+    if proto == "UNKNOWN" {
+        // the rest is ignored
+        return Some(None);
+    }
+    let [src, _dst, sport, _dport] = [parts.next()?, parts.next()?, parts.next()?, parts.next()?];
+    if parts.next().is_some() { return None; }
+    let src: IpAddr = match proto {
+        "TCP4" => src.parse::<Ipv4Addr>().ok()?.into(),
+        "TCP6" => src.parse::<Ipv6Addr>().ok()?.into(),
+        _ => return None,
+    };
+    // leading zeros are not allowed
+    if sport.starts_with('0') && sport != "0" { return None; }
+    Some(Some(SocketAddr::new(src, sport.parse().ok()?)))
+}
+
+/// Parses the v2 header after the signature: version and command, family, and the addresses
+fn parse_v2(ver_cmd: u8, family: u8, addrs: &[u8]) -> Option<Option<SocketAddr>> {
+    if ver_cmd >> 4 != 2 { return None; }
+    match ver_cmd & 0xf {
+        // LOCAL, like health checks from the proxy itself
+        0 => return Some(None),
+        // PROXY
+        1 => {}
+        _ => return None,
+    }
+    match family {
+        // TCP over IPv4
+        0x11 if addrs.len() >= 12 => {
+            let ip: [u8; 4] = addrs[..4].try_into().unwrap();
+            Some(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([addrs[8], addrs[9]]))))
+        }
+        // TCP over IPv6
+        0x21 if addrs.len() >= 36 => {
+            let ip: [u8; 16] = addrs[..16].try_into().unwrap();
+            Some(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([addrs[32], addrs[33]]))))
+        }
+        0x11 | 0x21 => None,
+        // UNSPEC and other protocols have no usable address
+        _ => Some(None),
+    }
+}
+
+/// Reads the header, and returns the client's address from it
+///
+/// `None` means that the proxy has not sent the address (like in `LOCAL` connections),
+/// so the connection's own address should be used.
+/// Reads exactly the header, nothing after it
+pub(crate) async fn read_header(conn: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0; 6];
+    conn.read_exact(&mut start).await?;
+
+    if &start == V1_START {
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() == V1_MAX_LEN { return Err(invalid()); }
+            line.push(conn.read_u8().await?);
+        }
+        let line = str::from_utf8(&line[6..line.len() - 2]).map_err(|_| invalid())?;
+        return parse_v1(line).ok_or_else(invalid);
+    }
+
+    if start == V2_SIGNATURE[..6] {
+        let mut header = [0; 10];
+        conn.read_exact(&mut header).await?;
+        if header[..6] != V2_SIGNATURE[6..] { return Err(invalid()); }
+        let len = u16::from_be_bytes([header[8], header[9]]);
+        let mut addrs = vec![0; len as usize];
+        conn.read_exact(&mut addrs).await?;
+        return parse_v2(header[6], header[7], &addrs).ok_or_else(invalid);
+    }
+
+    Err(invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(mut input: &[u8]) -> io::Result<Option<SocketAddr>> {
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let result = rt.block_on(read_header(&mut input));
+        // nothing after the header is consumed
+        if result.is_ok() { assert_eq!(input, b"GET"); }
+        result
+    }
+
+    fn addr(s: &str) -> Option<SocketAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn v1() {
+        assert_eq!(read(b"PROXY TCP4 10.0.0.1 10.0.0.2 51000 80\r\nGET").unwrap(), addr("10.0.0.1:51000"));
+        assert_eq!(read(b"PROXY TCP6 fd00::1 fd00::2 51000 443\r\nGET").unwrap(), addr("[fd00::1]:51000"));
+        assert_eq!(read(b"PROXY UNKNOWN\r\nGET").unwrap(), None);
+        assert_eq!(read(b"PROXY UNKNOWN fd00::1 fd00::2 51000 443\r\nGET").unwrap(), None);
+        for bad in [
+            &b"PROXY TCP4 fd00::1 10.0.0.2 51000 80\r\nGET"[..],
+            b"PROXY TCP4 10.0.0.1 10.0.0.2 051000 80\r\nGET",
+            b"PROXY TCP4 10.0.0.1 10.0.0.2 70000 80\r\nGET",
+            b"PROXY TCP4 10.0.0.1 10.0.0.2 51000\r\nGET",
+            b"PROXY TCP4  10.0.0.1 10.0.0.2 51000 80\r\nGET",
+            b"PROXY UDP4 10.0.0.1 10.0.0.2 51000 80\r\nGET",
+            b"GET / HTTP/1.1\r\n\r\n",
+        ] {
+            assert!(read(bad).is_err(), "{}", String::from_utf8_lossy(bad));
+        }
+        let long = format!("PROXY UNKNOWN {}\r\nGET", "a".repeat(100));
+        assert!(read(long.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn v2() {
+        let mut v4 = V2_SIGNATURE.to_vec();
+        v4.extend_from_slice(&[0x21, 0x11, 0, 15, 10, 0, 0, 1, 10, 0, 0, 2, 0xc7, 0x38, 0, 80]);
+        // a TLV is skipped
+        v4.extend_from_slice(&[0x04, 0, 0]);
+        v4.extend_from_slice(b"GET");
+        assert_eq!(read(&v4).unwrap(), addr("10.0.0.1:51000"));
+
+        let mut v6 = V2_SIGNATURE.to_vec();
+        v6.extend_from_slice(&[0x21, 0x21, 0, 36]);
+        v6.extend_from_slice(&"fd00::1".parse::<Ipv6Addr>().unwrap().octets());
+        v6.extend_from_slice(&[0; 16]);
+        v6.extend_from_slice(&[0xc7, 0x38, 1, 187]);
+        v6.extend_from_slice(b"GET");
+        assert_eq!(read(&v6).unwrap(), addr("[fd00::1]:51000"));
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        local.extend_from_slice(b"GET");
+        assert_eq!(read(&local).unwrap(), None);
+
+        let mut short = V2_SIGNATURE.to_vec();
+        short.extend_from_slice(&[0x21, 0x11, 0, 4, 10, 0, 0, 1]);
+        short.extend_from_slice(b"GET");
+        assert!(read(&short).is_err());
+        let mut version = V2_SIGNATURE.to_vec();
+        version.extend_from_slice(&[0x11, 0x00, 0, 0]);
+        assert!(read(&version).is_err());
+    }
+}
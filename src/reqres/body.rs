@@ -3,8 +3,10 @@ use std::fmt;
 use std::pin::Pin;
 
 use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 use crate::core::connection::HttpConnection;
+use crate::reqres::HttpHeader;
 use crate::util::escape;
 
 /// Http protocol upgrade
@@ -32,6 +34,77 @@ impl<T: HttpUpgrade> HttpUpgradeRaw for T {
     }
 }
 
+/// Streaming response body
+///
+/// Produces the body chunk by chunk, for bodies that are generated on the fly.
+/// Sent with `Transfer-Encoding: chunked` to HTTP/1.1 clients, HTTP/1.0 clients get
+/// the body until the connection is closed
+///
+/// Use in [`HttpBody::Stream`], or through [`res::stream`] if you have an [`AsyncRead`]
+///
+/// [`res::stream`]: crate::reqres::res::stream
+pub trait HttpStream: Send {
+    /// Produces the next chunk of the body, or `None` if the body has ended
+    ///
+    /// Equivalent signature:
+    /// `async fn next(&mut self) -> io::Result<Option<Vec<u8>>>`
+    fn next(&mut self) -> impl Future<Output = io::Result<Option<Vec<u8>>>> + Send;
+
+    /// Trailer fields to send after the body, for example a checksum of the streamed data
+    ///
+    /// Called once [`next`](HttpStream::next) returns `None`, empty by default
+    fn trailers(&mut self) -> Vec<HttpHeader> {
+        vec![]
+    }
+}
+
+type NextChunk<'a> = Pin<Box<dyn Future<Output = io::Result<Option<Vec<u8>>>> + Send + 'a>>;
+
+/// Dyn version of [`HttpStream`]
+pub trait HttpStreamRaw: Send {
+    /// Produces the next chunk of the body (dyn version)
+    fn next_raw(&mut self) -> NextChunk<'_>;
+    /// Trailer fields to send after the body (dyn version)
+    fn trailers_raw(&mut self) -> Vec<HttpHeader>;
+}
+
+impl<T: HttpStream> HttpStreamRaw for T {
+    fn next_raw(&mut self) -> NextChunk<'_> {
+        Box::pin(self.next())
+    }
+
+    fn trailers_raw(&mut self) -> Vec<HttpHeader> {
+        self.trailers()
+    }
+}
+
+/// Size of chunks produced by [`ReaderStream`]
+const READER_CHUNK: usize = 16384;
+
+/// [`HttpStream`] that reads from an [`AsyncRead`]
+pub(crate) struct ReaderStream<R> {
+    pub reader: R,
+}
+
+impl<R: AsyncRead + Unpin + Send> HttpStream for ReaderStream<R> {
+    async fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut chunk = vec![0; READER_CHUNK];
+        let len = self.reader.read(&mut chunk).await?;
+        if len == 0 { return Ok(None); }
+        chunk.truncate(len);
+        Ok(Some(chunk))
+    }
+}
+
+/// [`HttpStream`] of a single chunk
+pub(crate) struct BytesStream(pub Option<Vec<u8>>);
+
+impl HttpStream for BytesStream {
+    async fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.0.take())
+    }
+}
+
 /// Body of the response
 #[non_exhaustive]
 pub enum HttpBody {
@@ -39,6 +112,8 @@ pub enum HttpBody {
     Bytes(Vec<u8>),
     /// File handle to read
     File { file: File, len: u64 },
+    /// Body of unknown length, generated on the fly
+    Stream(Box<dyn HttpStreamRaw>),
     /// Protocol upgrade
     Upgrade(Box<dyn HttpUpgradeRaw>),
 }
@@ -48,6 +123,7 @@ impl fmt::Debug for HttpBody {
         match self {
             HttpBody::Bytes(v) => write!(fmt, r#"HttpBody::Bytes(b"{}")"#, escape::to_utf8(v)),
             HttpBody::File { file, len } => fmt.debug_struct("HttpBody::File").field("file", file).field("len", len).finish(),
+            HttpBody::Stream(_) => fmt.write_str("HttpBody::Stream(..)"),
             HttpBody::Upgrade(_) => fmt.write_str("HttpBody::Upgrade(..)"),
         }
     }
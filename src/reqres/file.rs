@@ -64,6 +64,7 @@ pub async fn file(req: &HttpRequest, name: &Path) -> HttpResult {
         headers,
         body: HttpBody::File { file, len },
         content_type,
+        trailers: vec![],
     })
 }
 
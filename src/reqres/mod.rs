@@ -3,9 +3,9 @@
 mod status_code;
 pub use status_code::StatusCode;
 mod req;
-pub use req::{HttpRequest, HttpVersion, HttpMethod};
-mod body;
-pub use body::{HttpBody, HttpUpgrade};
+pub use req::{HttpRequest, HttpVersion, HttpMethod, RequestTarget};
+pub(crate) mod body;
+pub use body::{HttpBody, HttpUpgrade, HttpStream};
 
 pub mod res;
 pub use res::HttpResponse;
@@ -1,5 +1,6 @@
 use std::fmt;
 use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, OnceLock};
 
 use crate::reqres::HttpHeader;
 
@@ -73,18 +74,56 @@ impl fmt::Display for HttpMethod {
     }
 }
 
+/// Form of the request-target in the request line (RFC 9112, section 3.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestTarget {
+    /// `/path?query`, the usual one
+    Origin,
+    /// `http://host/path?query`, sent to proxies
+    ///
+    /// Route contains only the path, and the authority replaces the `Host` header
+    Absolute,
+    /// `host:port`, only used with `CONNECT`
+    ///
+    /// Route is `/`, and the authority replaces the `Host` header
+    Authority,
+    /// `*`, used with `OPTIONS` to ask about the server itself
+    ///
+    /// Route is `*`, and it goes to the service like any other. [`DefaultService`](crate::services::DefaultService)
+    /// answers `OPTIONS *`, and [`Router`](crate::services::Router) only matches it with a `*` route.
+    /// Other methods are rejected in strict mode (lenient mode lets through `M-SEARCH` of SSDP)
+    Asterisk,
+}
+
 /// Request from client to handle
 #[derive(Debug, Clone)]
 #[non_exhaustive]
 pub struct HttpRequest {
     pub method: HttpMethod,
+    /// Path and query of the request, always starts with `/`
     pub route: String,
+    /// Form in which the route was sent
+    pub target: RequestTarget,
+    /// Request-target exactly as the client sent it, for logs (`:path` or `:authority` in HTTP/2)
+    pub raw_target: String,
     pub version: HttpVersion,
     pub headers: Vec<HttpHeader>,
-    /// Contents of the `Content-Length` header
-    pub len: u64,
-    /// IP address of this request (`0.0.0.0` if none)
+    /// Length of the body from the `Content-Length` header
+    ///
+    /// `None` if the length is unknown (`Transfer-Encoding: chunked`)
+    pub len: Option<u64>,
+    /// IP address of this request (`0.0.0.0` if none, `127.0.0.1` on Unix sockets)
     pub addr: IpAddr,
+    /// Port of the client (`0` if none)
+    pub port: u16,
+    /// `http` or `https`, trusted proxies may report it (see [`crate::forwarded`])
+    pub scheme: String,
+    /// `Host` header (or `:authority` in HTTP/2), trusted proxies may report it too
+    pub host: Option<String>,
+    /// ID of this request, from a trusted `X-Request-Id` or generated. It's sent back in `X-Request-Id`
+    pub id: String,
+    /// Trailer fields, set by the body reader once the body ends
+    pub(crate) trailers: Arc<OnceLock<Vec<HttpHeader>>>,
 }
 
 impl HttpRequest {
@@ -99,6 +138,25 @@ impl HttpRequest {
         }
         header
     }
+
+    /// Retrieves all values of a header that may be repeated
+    pub fn get_headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers.iter().filter(move |h| h.name.eq_ignore_ascii_case(name)).map(|h| h.value.as_str())
+    }
+
+    /// Trailer fields sent after the body
+    ///
+    /// Only chunked bodies can have trailers, and they are received after the body.
+    /// This is `None` until the chunked body was read till the end
+    pub fn trailers(&self) -> Option<&[HttpHeader]> {
+        self.trailers.get().map(Vec::as_slice)
+    }
+
+    /// Retrieves a trailer value, if any
+    pub fn get_trailer<'a>(&'a self, name: &str) -> Option<&'a str> {
+        let trailers = self.trailers()?;
+        trailers.iter().find(|h| h.name.eq_ignore_ascii_case(name)).map(|h| h.value.as_str())
+    }
 }
 
 impl Default for HttpRequest {
@@ -106,10 +164,17 @@ impl Default for HttpRequest {
         HttpRequest {
             method: HttpMethod::Get,
             route: String::new(),
+            target: RequestTarget::Origin,
+            raw_target: String::new(),
             version: HttpVersion { major: 0, minor: 0 },
             headers: vec![],
-            len: 0,
+            len: Some(0),
             addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 0,
+            scheme: "http".to_string(),
+            host: None,
+            id: String::new(),
+            trailers: Arc::new(OnceLock::from(vec![])),
         }
     }
 }
@@ -1,8 +1,10 @@
 //! HTTP response and its constructors
 
 use percent_encoding_lite::{is_encoded, encode, Bitmask};
+use tokio::io::AsyncRead;
 
-use crate::reqres::{HttpHeader, HttpBody, StatusCode};
+use crate::reqres::{HttpHeader, HttpBody, HttpStream, StatusCode};
+use crate::reqres::body::ReaderStream;
 use crate::reqres::sse::HttpSse;
 
 /// Your response
@@ -13,6 +15,10 @@ pub struct HttpResponse {
     pub headers: Vec<HttpHeader>,
     pub body: HttpBody,
     pub content_type: String,
+    /// Trailer fields, sent after the body
+    ///
+    /// Only sent to clients that accept them (`TE: trailers`), as the last part of a chunked body
+    pub trailers: Vec<HttpHeader>,
 }
 
 impl HttpResponse {
@@ -27,6 +33,14 @@ impl HttpResponse {
         self
     }
 
+    /// Pushes a new trailer field
+    ///
+    /// Trailers that are only known after streaming the body can be returned from [`HttpStream::trailers`]
+    pub fn add_trailer(&mut self, name: impl Into<String>, value: impl Into<String>) -> &mut HttpResponse {
+        self.trailers.push(HttpHeader { name: name.into(), value: value.into() });
+        self
+    }
+
     /// Constructs new response with a specified `Content-Type`
     pub fn with_type(content_type: impl Into<String>, body: impl Into<HttpBody>) -> HttpResponse {
         HttpResponse {
@@ -34,6 +48,7 @@ impl HttpResponse {
             headers: vec![],
             body: body.into(),
             content_type: content_type.into(),
+            trailers: vec![],
         }
     }
 }
@@ -76,6 +91,7 @@ pub fn redirect(dest: impl Into<String>) -> HttpResponse {
         headers: vec![HttpHeader { name: "Location".to_string(), value: dest.clone() }],
         body: format!("<a href=\"{dest}\">Click here if you weren't redirected</a>\n").into(),
         content_type: "text/html; charset=utf-8".to_string(),
+        trailers: vec![],
     }
 }
 
@@ -83,4 +99,16 @@ pub fn sse(handler: impl HttpSse) -> HttpResponse {
     HttpResponse::with_type("text/event-stream", HttpBody::Upgrade(Box::new(handler)))
 }
 
+/// Streams the body from an [`AsyncRead`] (`application/octet-stream`)
+///
+/// Set `res.content_type` to change the type
+pub fn stream(reader: impl AsyncRead + Unpin + Send + 'static) -> HttpResponse {
+    stream_with(ReaderStream { reader })
+}
+
+/// Streams the body from an [`HttpStream`] producer (`application/octet-stream`)
+pub fn stream_with(stream: impl HttpStream + 'static) -> HttpResponse {
+    HttpResponse::with_type("application/octet-stream", HttpBody::Stream(Box::new(stream)))
+}
+
 pub use super::file::file;
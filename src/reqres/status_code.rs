@@ -25,9 +25,13 @@ impl StatusCode {
             403 => "Forbidden",
             404 => "Not found",
             405 => "Method not allowed",
+            408 => "Request timeout",
             413 => "Request entity too large",
+            414 => "URI too long",
             416 => "Range not satisfiable",
+            431 => "Request header fields too large",
             500 => "Internal server error",
+            503 => "Service unavailable",
             505 => "HTTP version not supported",
             _ => "Unknown",
         }
@@ -59,15 +63,23 @@ impl StatusCode {
     pub const NOT_FOUND: StatusCode = StatusCode(404);
     /// 405
     pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    /// 408
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
     /// 413
     pub const REQUEST_ENTITY_TOO_LARGE: StatusCode = StatusCode(413);
+    /// 414
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
     /// 416
     pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    /// 431
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
 
     // 5xx
 
     /// 500
     pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    /// 503
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
     /// 505
     pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
 }
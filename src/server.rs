@@ -3,25 +3,113 @@
 use std::io;
 use std::sync::Arc;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
-use tokio::io::{BufReader, AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpSocket;
+use tokio::io::{AsyncRead, BufReader, AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
 use socket2::SockRef;
 
-use crate::h1::{self, HttpRequestError};
-use crate::reqres::{HttpRequest, StatusCode};
-use crate::core::{HttpService, HttpServiceRaw, HttpErrorHandler, HttpErrorType, HttpLogger};
-use crate::core::connection::{HttpConnection, EmitContinue};
+use crate::h1::{self, HttpRequestError, BodyReader};
+use crate::h2;
+use crate::forwarded::{self, ForwardedHeader};
+use crate::proxy;
+use crate::reqres::{HttpRequest, HttpResponse, HttpBody, HttpMethod, StatusCode};
+use crate::core::{HttpService, HttpServiceRaw, HttpLayer, Layered, HttpError, HttpErrorHandler, HttpErrorType, HttpLogger, HttpLogContext};
+use crate::core::connection::{HttpConnection, HttpRead, EmitContinue, Timeout};
 use crate::services::{DefaultService, DefaultLogger, ErrorPageHandler};
+use crate::util::cidr::Cidr;
 use crate::util::future::Or;
+use crate::util::limit::Limit;
+use crate::util::request_id;
 
 const DEFAULT_MAX_HEADERS_SIZE: u64 = 65536; // 64KB
+const DEFAULT_MAX_HEADER_COUNT: usize = 100;
+const DEFAULT_MAX_HEADER_LINE: usize = 8192;
+const DEFAULT_MAX_URI_LEN: usize = 8192;
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_BODY_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_MAX_REQUESTS: usize = 1000;
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
 
-/// An HTTP/1.1 server
+/// Check of [`HttpServer::trust_request_id`], gets the request and its `X-Request-Id`
+pub type RequestIdCheck = Box<dyn Fn(&HttpRequest, &str) -> bool + Send + Sync>;
+
+/// An HTTP/1.1 and HTTP/2 server
 pub struct HttpServer {
     pub name: String,
+    /// Limit of all headers together, with the request line, `431 Request header fields too large` is sent if exceeded
     pub max_headers_size: u64,
+    /// Limit of the number of headers (`431`)
+    pub max_header_count: usize,
+    /// Limit of a single header line (`431`)
+    pub max_header_line: usize,
+    /// Limit of the request route (`414 URI too long`)
+    pub max_uri_len: usize,
+    /// Reject requests that are ambiguous according to RFC 9112 with `400 Bad request`
+    ///
+    /// Use it behind proxies and CDNs to prevent request smuggling. Strict mode rejects:
+    /// - line endings other than `\r\n` and bare `\r`
+    /// - obsolete line folding, non-token header names and methods
+    /// - control characters in header values and route
+    /// - missing or repeated `Host` in HTTP/1.1
+    /// - repeated `Content-Length`, and `Content-Length` together with `Transfer-Encoding`
+    /// - `CONNECT` without an authority-form target (`host:port`)
+    /// - `*` target with methods other than `OPTIONS`
+    ///
+    /// Trailer fields of chunked bodies are checked the same way as headers.
+    /// Whitespace between a header name and the colon is rejected in both modes: such a header
+    /// used to be kept under a name with trailing space, which other servers may still read
+    pub strict: bool,
+    /// Time limit for receiving the request line and headers, `408 Request timeout` is sent after it
+    pub header_timeout: Option<Duration>,
+    /// How long the service can wait for the next piece of request body
+    pub body_timeout: Option<Duration>,
+    /// How long a response write can stall until the connection is dropped
+    pub write_timeout: Option<Duration>,
+    /// How long a persistent connection can stay idle between requests
+    pub keep_alive_timeout: Option<Duration>,
+    /// Maximum number of requests served on one connection
+    pub max_requests: Option<usize>,
+    /// Stops the server gracefully
+    pub shutdown: Shutdown,
+    /// Triggers [`HttpServer::shutdown`] on Ctrl+C. Off by default, the process is just killed then
+    pub shutdown_on_ctrl_c: bool,
+    /// How long in-flight requests can take after shutdown, remaining connections are dropped after it
+    pub shutdown_timeout: Option<Duration>,
+    /// Limit of open connections, accepting is paused until one of them is closed
+    ///
+    /// Counted together for all listeners that share this server
+    pub max_connections: Option<usize>,
+    /// Limit of requests handled at once, `503 Service unavailable` is sent to the ones over it
+    pub max_in_flight: Option<usize>,
+    /// `Retry-After` of the `503` responses
+    pub retry_after: Duration,
+    pub(crate) connections: Arc<Limit>,
+    pub(crate) in_flight: Arc<Limit>,
+    /// Networks of proxies that start their connections with the PROXY protocol header (v1 or v2)
+    ///
+    /// Client address from the header becomes [`HttpRequest::addr`] and `port`.
+    /// Connections from these networks without a valid header are dropped, other ones can't send it.
+    /// Only for TCP listeners, with or without TLS
+    pub proxy_protocol: Vec<Cidr>,
+    /// Networks of reverse proxies, their forwarding headers replace [`HttpRequest::addr`], `scheme` and `host`
+    ///
+    /// See [`crate::forwarded`]. Trust only the proxies you run, and make sure clients can't bypass them
+    pub trusted_proxies: Vec<Cidr>,
+    /// Forwarding header of [`HttpServer::trusted_proxies`]
+    pub forwarded_header: ForwardedHeader,
+    /// Decides if `X-Request-Id` from the client can be used as [`HttpRequest::id`], new ID is generated otherwise
+    ///
+    /// Only short IDs of letters, digits and `-_.:+/=@` get here. Nothing is trusted by default
+    pub trust_request_id: RequestIdCheck,
+    /// Accept HTTP/2: with prior knowledge or `Upgrade: h2c` on cleartext connections, and with ALPN on TLS
+    pub http2: bool,
     pub service: Box<dyn HttpServiceRaw>,
     pub error_handler: Box<dyn HttpErrorHandler>,
     pub logger: Box<dyn HttpLogger>,
@@ -32,6 +120,28 @@ impl HttpServer {
         HttpServer {
             name: "DrakoHTTP".to_string(),
             max_headers_size: DEFAULT_MAX_HEADERS_SIZE,
+            max_header_count: DEFAULT_MAX_HEADER_COUNT,
+            max_header_line: DEFAULT_MAX_HEADER_LINE,
+            max_uri_len: DEFAULT_MAX_URI_LEN,
+            strict: false,
+            header_timeout: Some(DEFAULT_HEADER_TIMEOUT),
+            body_timeout: Some(DEFAULT_BODY_TIMEOUT),
+            write_timeout: Some(DEFAULT_WRITE_TIMEOUT),
+            keep_alive_timeout: Some(DEFAULT_KEEP_ALIVE_TIMEOUT),
+            max_requests: Some(DEFAULT_MAX_REQUESTS),
+            shutdown: Shutdown::new(),
+            shutdown_on_ctrl_c: false,
+            shutdown_timeout: Some(DEFAULT_SHUTDOWN_TIMEOUT),
+            max_connections: None,
+            max_in_flight: None,
+            retry_after: DEFAULT_RETRY_AFTER,
+            connections: Arc::default(),
+            in_flight: Arc::default(),
+            proxy_protocol: vec![],
+            trusted_proxies: vec![],
+            forwarded_header: ForwardedHeader::XForwardedFor,
+            trust_request_id: Box::new(|_req, _id| false),
+            http2: true,
             service: Box::new(DefaultService),
             error_handler: Box::new(ErrorPageHandler { name: "DrakoHTTP".to_string() }),
             logger: Box::new(DefaultLogger),
@@ -43,6 +153,13 @@ impl HttpServer {
         self
     }
 
+    /// Wraps the current service in a layer, layers added later see the request first
+    pub fn layer(&mut self, layer: impl HttpLayer) -> &mut Self {
+        let inner = std::mem::replace(&mut self.service, Box::new(DefaultService));
+        self.service = Box::new(Layered { layer, inner });
+        self
+    }
+
     pub fn error_handler(&mut self, error_handler: impl HttpErrorHandler) -> &mut Self {
         self.error_handler = Box::new(error_handler);
         self
@@ -52,6 +169,12 @@ impl HttpServer {
         self.logger = Box::new(logger);
         self
     }
+
+    /// Sets [`HttpServer::trust_request_id`], like `|req, _id| req.addr.is_loopback()`
+    pub fn trust_request_id(&mut self, check: impl Fn(&HttpRequest, &str) -> bool + Send + Sync + 'static) -> &mut Self {
+        self.trust_request_id = Box::new(check);
+        self
+    }
 }
 
 impl Default for HttpServer {
@@ -60,35 +183,274 @@ impl Default for HttpServer {
     }
 }
 
+/// Graceful shutdown handle
+///
+/// Once triggered, listeners stop accepting, idle connections are closed,
+/// and in-flight requests are finished with `Connection: close` (or `GOAWAY` in HTTP/2).
+/// Clones trigger the same server
+///
+/// ```no_run
+/// # use dhttp::server::HttpServer;
+/// # use tokio::signal::unix::{signal, SignalKind};
+/// # async fn f(server: HttpServer) -> std::io::Result<()> {
+/// let mut sigterm = signal(SignalKind::terminate())?;
+/// server.shutdown.trigger_on(async move { sigterm.recv().await; });
+/// dhttp::serve_tcp("[::]:8080", server).await
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Shutdown(Arc<watch::Sender<bool>>);
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown(Arc::new(watch::Sender::new(false)))
+    }
+
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+
+    /// Triggers the shutdown when `signal` completes. Must be called inside the tokio runtime
+    pub fn trigger_on(&self, signal: impl Future<Output = ()> + Send + 'static) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            signal.await;
+            shutdown.trigger();
+        });
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Completes once the shutdown is triggered
+    pub async fn wait(&self) {
+        let mut rx = self.0.subscribe();
+        // sender can't be dropped, we have it
+        let _ = rx.wait_for(|&triggered| triggered).await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        Shutdown::new()
+    }
+}
+
+/// Response of the service, it's logged after sending
+pub(crate) struct Exchange {
+    pub(crate) res: HttpResponse,
+    /// Errors with a description are logged with [`HttpLogger::err`]
+    error: Option<Box<dyn HttpError>>,
+    start: SystemTime,
+    timer: Instant,
+}
+
 impl HttpServer {
-    async fn handle_connection(&self, mut conn: impl HttpConnection) -> io::Result<()> {
+    /// Error page for errors detected by the connection handler
+    pub(crate) fn plain_error(&self, code: StatusCode) -> HttpResponse {
+        let mut res = self.error_handler.plain_code(code);
+        // Error handler does not set the code (see `HttpErrorHandler`)
+        res.code = code;
+        res
+    }
+
+    /// Advertises the limits of a persistent connection
+    fn keep_alive_header(&self, res: &mut HttpResponse, requests: usize) {
+        let mut params = vec![];
+        // Rounded down, so clients stop before the server does. Less than a second is not worth reusing
+        if let Some(timeout) = self.keep_alive_timeout.filter(|t| t.as_secs() > 0) {
+            params.push(format!("timeout={}", timeout.as_secs()));
+        }
+        if let Some(max) = self.max_requests {
+            params.push(format!("max={}", max - requests));
+        }
+        if !params.is_empty() {
+            res.add_header("Keep-Alive", params.join(", "));
+        }
+    }
+
+    /// Response to requests over [`HttpServer::max_in_flight`]
+    fn overloaded(&self, req: &HttpRequest) -> HttpResponse {
+        let mut res = self.error_handler.plain_code_for(req, StatusCode::SERVICE_UNAVAILABLE);
+        res.code = StatusCode::SERVICE_UNAVAILABLE;
+        res.add_header("Retry-After", ceil_secs(self.retry_after).to_string());
+        res
+    }
+
+    /// Runs the service, errors are turned into responses by the error handler
+    ///
+    /// Returns `None` on fatal errors, then the connection (or HTTP/2 stream) has to be dropped
+    pub(crate) async fn respond(&self, req: &HttpRequest, body: &mut dyn HttpRead) -> Option<Exchange> {
+        let start = SystemTime::now();
+        let timer = Instant::now();
+
+        // Before executing the service, we have to check if request is compatible
+        // This is connection handler's responsibility
+        let res = match self.in_flight.try_acquire(self.max_in_flight) {
+            // Counted until the service returns, streamed bodies are not limited
+            Some(_permit) => match self.service.filter_raw(&req.route, req) {
+                Ok(()) => self.service.request_raw(&req.route, req, body).await,
+                Err(err) => Err(err),
+            },
+            None => Ok(self.overloaded(req)),
+        };
+
+        let (mut res, error) = match res {
+            Ok(res) => (res, None),
+            Err(err) => {
+                let code = err.status_code();
+                // Response is Err, should be handled with defined error handler
+                let (mut handled, error) = match err.error_type() {
+                    // IO error
+                    HttpErrorType::Fatal => return None,
+                    // Status code, logged like a response
+                    HttpErrorType::Hidden => (self.error_handler.plain_code_for(req, code), None),
+                    // Error with description, logged as an error
+                    HttpErrorType::User => (self.error_handler.error(req, err.as_ref()), Some(err)),
+                };
+                // Always use the original status code in the error response (connection handler sets this)
+                handled.code = code;
+                (handled, error)
+            }
+        };
+
+        // Add our server name
+        if !self.name.is_empty() {
+            res.add_header("Server", &self.name);
+        }
+        if !req.id.is_empty() {
+            res.add_header("X-Request-Id", &req.id);
+        }
+        Some(Exchange { res, error, start, timer })
+    }
+
+    /// Logs the exchange once its response is sent (or has failed to)
+    pub(crate) fn log(&self, req: &HttpRequest, ex: &Exchange, request_number: usize, bytes_sent: u64, aborted: bool) {
+        let ctx = HttpLogContext { start: ex.start, duration: ex.timer.elapsed(), bytes_sent, request_number, aborted };
+        match &ex.error {
+            Some(err) => self.logger.err(req, &ex.res, err.as_ref(), &ctx),
+            None => self.logger.log(req, &ex.res, &ctx),
+        }
+    }
+
+    /// Sets the client's address, scheme, host and request ID, they have to be set by the connection handler
+    pub(crate) fn set_client(&self, req: &mut HttpRequest, peer: Option<SocketAddr>, secure: bool) {
+        if let Some(peer) = peer {
+            req.addr = peer.ip().to_canonical();
+            req.port = peer.port();
+        }
+        req.scheme = if secure { "https" } else { "http" }.to_string();
+        req.host = req.get_header("Host").map(String::from);
+        forwarded::resolve(req, &self.trusted_proxies, self.forwarded_header);
+
+        req.id = match req.get_header("X-Request-Id") {
+            Some(id) if request_id::is_valid(id) && (self.trust_request_id)(req, id) => id.to_string(),
+            _ => request_id::generate(),
+        };
+    }
+
+    /// Reads the PROXY protocol header if the connection comes from a trusted proxy
+    ///
+    /// Returns the client's address from it. On errors, the connection has to be dropped
+    pub(crate) async fn proxy_header(&self, conn: &mut (impl AsyncRead + Unpin), peer: io::Result<SocketAddr>) -> io::Result<Option<SocketAddr>> {
+        let Ok(peer) = peer else { return Ok(None) };
+        if !self.proxy_protocol.iter().any(|net| net.contains(peer.ip())) {
+            return Ok(None);
+        }
+        let header = proxy::read_header(conn);
+        match self.header_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, header).await.unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+            None => header.await,
+        }
+    }
+
+    /// `peer` replaces the connection's address, see [`HttpServer::proxy_protocol`]
+    pub(crate) async fn handle_connection(&self, conn: impl HttpConnection, peer: Option<SocketAddr>) -> io::Result<()> {
+        let mut conn = Timeout::new(conn);
+        conn.write_timeout = self.write_timeout;
+        conn.peer = peer;
+
         let mut connection_close = false;
+        let mut requests = 0;
         while !connection_close {
-            let req = h1::read((&mut conn).take(self.max_headers_size)).await;
+            // Wait for the next request on a persistent connection
+            if requests > 0 {
+                let next = conn.fill_buf();
+                let next = async {
+                    match self.keep_alive_timeout {
+                        Some(timeout) => tokio::time::timeout(timeout, next).await.unwrap_or(Ok(&[])),
+                        None => next.await,
+                    }
+                };
+                let eof = match Or::new(next, self.shutdown.wait()).await {
+                    Ok(data) => data?.is_empty(),
+                    Err(()) => true,
+                };
+                // Client has closed the connection, kept it idle for too long, or server is shutting down
+                if eof { break; }
+            }
+            requests += 1;
+
+            let deadline = self.header_timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+            if requests == 1 {
+                // Connection that hasn't sent anything yet is closed on shutdown, like an idle one
+                let first = async {
+                    match deadline {
+                        // 408 is sent below
+                        Some(deadline) => tokio::time::timeout_at(deadline, conn.fill_buf()).await.map_or(Ok(()), |data| data.map(drop)),
+                        None => conn.fill_buf().await.map(drop),
+                    }
+                };
+                match Or::new(first, self.shutdown.wait()).await {
+                    Ok(result) => result?,
+                    Err(()) => break,
+                }
+            }
+
+            let req = h1::read(&mut conn, self);
+            let req = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, req).await,
+                None => Ok(req.await),
+            };
+            let Ok(req) = req else {
+                // Client is too slow, close the connection
+                let mut res = self.plain_error(StatusCode::REQUEST_TIMEOUT);
+                h1::send(&HttpRequest::default(), &mut res, &mut conn).await?;
+                return conn.shutdown().await;
+            };
             if let Err(err) = req {
                 if let HttpRequestError::Io(err) = err {
                     // IO errors should not be handler
                     return Err(err);
                 } else {
-                    // Could not parse request, return Bad request
-                    let res = self.error_handler.plain_code(StatusCode::BAD_REQUEST);
-                    h1::send(&HttpRequest::default(), res, &mut conn).await?;
+                    // Could not parse request, return Bad request (or a more specific code)
+                    let mut res = self.plain_error(err.status_code());
+                    h1::send(&HttpRequest::default(), &mut res, &mut conn).await?;
                     return conn.shutdown().await;
                 }
             }
             // Request is Ok
             let mut req = req.unwrap();
 
-            // Address has to be set by the connection handler
-            if let Ok(addr) = conn.getpeername() {
-                req.addr = addr.ip().to_canonical();
+            // HTTP/2 prior knowledge headers look like `PRI * HTTP/2.0`
+            if self.http2 && requests == 1 && h2::is_prior_knowledge(&req) {
+                return h2::serve_prior_knowledge(self, &mut conn).await;
             }
 
-            // HTTP/2 prior knowledge headers look like `PRI * HTTP/2.0`
-            // These connections are not supported
+            self.set_client(&mut req, conn.getpeername().ok(), conn.is_secure());
+
+            // `Upgrade: h2c` with settings in `HTTP2-Settings`, which is listed in `Connection`
+            if self.http2 && !conn.is_secure() && let Some(settings) = h2::upgrade_settings(&req) {
+                return h2::serve_upgrade(self, &mut conn, req, settings).await;
+            }
+
+            // Headers named in `Connection` are meant for this hop only
+            h1::strip_connection_headers(&mut req.headers);
+
             if req.version.major != 1 {
-                let res = self.error_handler.plain_code(StatusCode::HTTP_VERSION_NOT_SUPPORTED);
-                h1::send(&req, res, &mut conn).await?;
+                let mut res = self.plain_error(StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+                h1::send(&req, &mut res, &mut conn).await?;
                 return conn.shutdown().await;
             }
 
@@ -96,8 +458,10 @@ impl HttpServer {
             // Otherwise, it will wait for a timeout
             // This adapter echoes `100 Continue` when service starts reading the body
             // (meaning, that service has accepted it)
+            // Service should not wait for the body forever
+            conn.set_read_timeout(self.body_timeout);
             let mut body = EmitContinue {
-                conn: (&mut conn).take(req.len),
+                conn: BodyReader::new(&mut conn, req.len, Arc::clone(&req.trailers), self.strict),
                 to_send: b"",
             };
             let expect = req.get_header("Expect");
@@ -105,74 +469,67 @@ impl HttpServer {
                 body.to_send = b"HTTP/1.1 100 Continue\r\n\r\n";
             }
 
-            // Future TODO: HTTP/1.1 connection handler has a lot of hardcoded functionality
-            // that still applies to HTTP/2 and QUIC. Some logic here could be separated
-
-            // Before executing the service, we have to check if request is compatible
-            // This is connection handler's responsibility
-            let mut res = match self.service.filter_raw(&req.route, &req) {
-                Ok(()) => self.service.request_raw(&req.route, &req, &mut body).await,
-                Err(err) => Err(err),
+            let Some(mut ex) = self.respond(&req, &mut body).await else {
+                // IO error
+                return conn.shutdown().await;
             };
-
-            if let Ok(res) = &res {
-                // Log request+response with our defined logger
-                self.logger.log(&req, res);
-            } else if let Err(err) = res {
-                // Response is Err, should be handled with defined error handler
-                let mut handled = match err.error_type() {
-                    // IO error
-                    HttpErrorType::Fatal => return conn.shutdown().await,
-                    // Status code
-                    HttpErrorType::Hidden => self.error_handler.plain_code(err.status_code()),
-                    // Error with description
-                    HttpErrorType::User => self.error_handler.error(&req, err.as_ref()),
-                };
-                // Always use the original status code in the error response (connection handler sets this)
-                handled.code = err.status_code();
-                // Log the error
-                match err.error_type() {
-                    HttpErrorType::Fatal => unreachable!(),
-                    HttpErrorType::Hidden => self.logger.log(&req, &handled),
-                    HttpErrorType::User => self.logger.err(&req, &handled, err.as_ref()),
-                };
-                res = Ok(handled);
-            }
-            // Response is Ok
-            let mut res = res.unwrap();
-
-            // Add our server name
-            if !self.name.is_empty() {
-                res.add_header("Server", &self.name);
-            }
+            let res = &mut ex.res;
 
             // Stop pipelining if:
+            // - connection has reached its request limit
             // - service didn't consume the body completely
-            // - HTTP/1.0 (doesn't support pipelining)
-            // - HTTP/1.1 but client didn't add `Connection: keep-alive`
-            if body.conn.limit() != 0 || req.version.is(1, 0) {
+            // - both `Transfer-Encoding` and `Content-Length` were sent (could be a smuggling attempt)
+            // - client has asked to close it (or HTTP/1.0 client didn't ask to keep it)
+            // - body is close-delimited (streaming to HTTP/1.0) or an upgrade
+            // - service has asked to close it
+            // - server is shutting down
+            let ambiguous = req.len.is_none() && req.get_header("Content-Length").is_some();
+            let limit_reached = self.max_requests.is_some_and(|max| requests >= max);
+            let persistent = if h1::can_chunk(&req) {
+                // HTTP/1.1 connections are persistent by default
+                !req.get_headers("Connection").any(|c| h1::has_token(c, "close"))
+            } else {
+                req.get_headers("Connection").any(|c| h1::has_token(c, "keep-alive"))
+            };
+            let close_delimited = match res.body {
+                // HEAD has no body to delimit
+                HttpBody::Stream(_) => !h1::can_chunk(&req) && req.method != HttpMethod::Head,
+                HttpBody::Upgrade(_) => true,
+                _ => false,
+            };
+            let service_close = res.headers.iter().any(|h| h.name.eq_ignore_ascii_case("Connection") && h1::has_token(&h.value, "close"));
+            // We set this header ourselves
+            res.headers.retain(|h| !h.name.eq_ignore_ascii_case("Connection"));
+
+            let shutdown = self.shutdown.is_triggered();
+            if limit_reached || !body.conn.is_finished() || ambiguous || !persistent || close_delimited || service_close || shutdown {
                 res.add_header("Connection", "close");
                 connection_close = true;
-            } else if req.version.major == 1 {
-                let connection = req.get_header("Connection");
-                if connection.is_some() && connection.unwrap().eq_ignore_ascii_case("keep-alive") {
-                    res.add_header("Connection", "keep-alive");
-                } else {
-                    res.add_header("Connection", "close");
-                    connection_close = true;
-                }
+            } else {
+                res.add_header("Connection", "keep-alive");
+                self.keep_alive_header(res, requests);
             }
 
-            // Now, send the response
-            h1::send(&req, res, &mut conn).await?;
+            // Upgrade handlers may wait for the client for as long as they want
+            conn.set_read_timeout(None);
+
+            // Now, send the response, and log it
+            let result = h1::send(&req, &mut ex.res, &mut conn).await;
+            self.log(&req, &ex, requests, conn.written, result.is_err());
+            result?;
         }
         // Loop ended, we close the connection now
         conn.shutdown().await
     }
 }
 
-/// Starts handling connections on a given [`HttpServer`], without TLS
-pub async fn serve_tcp(addr: &str, server: impl Into<Arc<HttpServer>>) -> io::Result<()> {
+/// Whole seconds for headers, rounded up so short durations don't become 0
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Creates the listening socket
+fn bind(addr: &str) -> io::Result<TcpListener> {
     let addr: SocketAddr = addr.parse().map_err(io::Error::other)?;
 
     let sock = match addr {
@@ -194,27 +551,61 @@ pub async fn serve_tcp(addr: &str, server: impl Into<Arc<HttpServer>>) -> io::Re
 
     sock.bind(addr)?;
 
-    let tcp = sock.listen(128)?;
-    let server = server.into();
+    sock.listen(128)
+}
+
+/// Listening socket, TCP or Unix
+pub(crate) trait Listener {
+    type Conn;
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<Self::Conn>>;
+}
+
+impl Listener for TcpListener {
+    type Conn = TcpStream;
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<TcpStream>> {
+        TcpListener::poll_accept(self, cx).map_ok(|(conn, _addr)| conn)
+    }
+}
+
+/// Accepts connections until shutdown, then waits for the running ones
+pub(crate) async fn accept_loop<L: Listener, F>(listener: L, server: &HttpServer, mut on_conn: impl FnMut(L::Conn) -> F) -> io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut connections = JoinSet::new();
     let mut err_shown = false;
     loop {
+        // Connections over the limit wait in the backlog
+        let accept = async {
+            let permit = server.connections.acquire(server.max_connections).await;
+            std::future::poll_fn(|cx| listener.poll_accept(cx)).await.map(|conn| (conn, permit))
+        };
         // This way, shutdown is handled gracefully
-        let result = Or::new(tcp.accept(), tokio::signal::ctrl_c()).await;
-        if result.is_err() { break; }
+        let ctrl_c = async {
+            match server.shutdown_on_ctrl_c {
+                true => { let _ = tokio::signal::ctrl_c().await; }
+                false => std::future::pending().await,
+            }
+        };
+        let stop = Or::new(server.shutdown.wait(), ctrl_c);
+        let Ok(result) = Or::new(accept, stop).await else { break };
+
+        // Forget the finished connections
+        while connections.try_join_next().is_some() {}
 
-        match result.unwrap() {
-            Ok((conn, _addr)) => {
+        match result {
+            Ok((conn, permit)) => {
                 err_shown = false;
-                let server2 = Arc::clone(&server);
-                tokio::spawn(async move {
-                    // ignore network errors
-                    let _ = server2.handle_connection(BufReader::new(conn)).await;
+                let task = on_conn(conn);
+                connections.spawn(async move {
+                    task.await;
+                    drop(permit);
                 });
             }
             Err(e) => {
                 // this may fire when fd limit is exhausted
                 if !err_shown {
-                    println!("DrakoHTTP critical error: connection not accepted: {e}");
+                    server.logger.accept_err(&e);
                     err_shown = true;
                 }
                 let d = Duration::from_millis(100);
@@ -223,9 +614,217 @@ pub async fn serve_tcp(addr: &str, server: impl Into<Arc<HttpServer>>) -> io::Re
         };
     }
 
+    // Stop accepting, and tell the connections to finish (Ctrl+C does not trigger it by itself)
+    drop(listener);
+    server.shutdown.trigger();
+    let drain = async {
+        while connections.join_next().await.is_some() {}
+    };
+    match server.shutdown_timeout {
+        Some(timeout) => { let _ = tokio::time::timeout(timeout, drain).await; }
+        None => drain.await,
+    }
+    // Connections that are still running are aborted when the set is dropped
     Ok(())
 }
 
+/// Listening socket of [`HttpListener`]
+pub(crate) enum Socket {
+    Tcp(TcpListener),
+    /// Socket file is removed after serving if the path is set
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, Option<std::path::PathBuf>),
+}
+
+/// Bound socket that is ready to serve
+///
+/// Allows to learn the address before serving, like an ephemeral port from `:0`:
+///
+/// ```no_run
+/// # use dhttp::server::{HttpServer, HttpListener};
+/// # async fn f(server: HttpServer) -> std::io::Result<()> {
+/// let listener = HttpListener::bind("127.0.0.1:0")?;
+/// println!("Listening on {}", listener.local_addr()?);
+/// listener.serve(server).await
+/// # }
+/// ```
+pub struct HttpListener {
+    pub(crate) socket: Socket,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<crate::tls::TlsConfig>,
+}
+
+impl HttpListener {
+    pub(crate) fn new(socket: Socket) -> HttpListener {
+        HttpListener {
+            socket,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Creates a listening TCP socket, this is what [`serve_tcp`] does
+    ///
+    /// Must be called inside the tokio runtime
+    pub fn bind(addr: &str) -> io::Result<HttpListener> {
+        Ok(HttpListener::new(Socket::Tcp(bind(addr)?)))
+    }
+
+    /// Takes a TCP socket that was bound and configured elsewhere, it has to be listening already
+    ///
+    /// Must be called inside the tokio runtime
+    pub fn from_std(listener: std::net::TcpListener) -> io::Result<HttpListener> {
+        listener.set_nonblocking(true)?;
+        Ok(HttpListener::new(Socket::Tcp(TcpListener::from_std(listener)?)))
+    }
+
+    /// Takes a listening TCP socket by its file descriptor, like one inherited from the parent process
+    ///
+    /// # Safety
+    /// `fd` must be an open TCP socket, and nothing else can own it
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(fd: std::os::fd::RawFd) -> io::Result<HttpListener> {
+        use std::os::fd::FromRawFd;
+        // SAFETY: guaranteed by the caller
+        HttpListener::from_std(unsafe { std::net::TcpListener::from_raw_fd(fd) })
+    }
+
+    /// Address of a TCP socket, Unix sockets don't have it
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.socket {
+            Socket::Tcp(tcp) => tcp.local_addr(),
+            #[cfg(unix)]
+            Socket::Unix(..) => Err(io::Error::new(io::ErrorKind::Unsupported, "Unix socket has no IP address")),
+        }
+    }
+
+    /// Starts handling connections on a given [`HttpServer`]
+    pub async fn serve(self, server: impl Into<Arc<HttpServer>>) -> io::Result<()> {
+        let server = server.into();
+        match self.socket {
+            Socket::Tcp(tcp) => {
+                #[cfg(feature = "tls")]
+                if let Some(tls) = self.tls {
+                    return crate::tls::serve(tcp, &server, tls).await;
+                }
+                accept_loop(tcp, &server, |conn| {
+                    let server = Arc::clone(&server);
+                    async move {
+                        let peer = conn.peer_addr();
+                        let mut conn = BufReader::new(conn);
+                        // drop connections with invalid headers
+                        let Ok(peer) = server.proxy_header(&mut conn, peer).await else { return };
+                        serve_conn(&server, conn, peer).await;
+                    }
+                }).await
+            }
+            #[cfg(unix)]
+            Socket::Unix(listener, path) => {
+                let result = accept_loop(listener, &server, |conn| serve_conn(&server, BufReader::new(conn), None)).await;
+                if let Some(path) = path {
+                    let _ = std::fs::remove_file(path);
+                }
+                result
+            }
+        }
+    }
+}
+
+impl From<TcpListener> for HttpListener {
+    fn from(tcp: TcpListener) -> HttpListener {
+        HttpListener::new(Socket::Tcp(tcp))
+    }
+}
+
+/// Task of an accepted connection
+pub(crate) fn serve_conn(server: &Arc<HttpServer>, conn: impl HttpConnection + 'static, peer: Option<SocketAddr>) -> impl Future<Output = ()> + Send + 'static {
+    let server = Arc::clone(server);
+    async move {
+        // ignore network errors
+        let _ = server.handle_connection(conn, peer).await;
+    }
+}
+
+/// Serves several listeners at once, like IPv4 and IPv6, or a public and an admin port
+///
+/// Every listener has its own [`HttpServer`], or shares one with others.
+/// When one of the listeners stops, like when its server is shut down, all the servers are shut down too.
+/// Then every listener drains its own connections within its server's [`HttpServer::shutdown_timeout`],
+/// and [`ListenerGroup::serve`] returns once all of them are done. A slow connection on one listener
+/// doesn't cut the drain of others short, and it isn't cut short by them either
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use dhttp::server::{HttpServer, HttpListener, ListenerGroup};
+/// # async fn f(public: HttpServer, admin: HttpServer) -> std::io::Result<()> {
+/// let public = Arc::new(public);
+/// let mut group = ListenerGroup::new();
+/// group.add(HttpListener::bind("0.0.0.0:8080")?, Arc::clone(&public));
+/// group.add(HttpListener::bind("[::]:8080")?, public);
+/// group.add(HttpListener::bind("127.0.0.1:9000")?, admin);
+/// group.serve().await
+/// # }
+/// ```
+pub struct ListenerGroup {
+    listeners: Vec<(HttpListener, Arc<HttpServer>)>,
+}
+
+impl ListenerGroup {
+    pub fn new() -> ListenerGroup {
+        ListenerGroup { listeners: vec![] }
+    }
+
+    /// Pass clones of one `Arc<HttpServer>` to share it between listeners
+    pub fn add(&mut self, listener: HttpListener, server: impl Into<Arc<HttpServer>>) -> &mut Self {
+        self.listeners.push((listener, server.into()));
+        self
+    }
+
+    /// Serves all listeners until shutdown, returns the first error
+    pub async fn serve(self) -> io::Result<()> {
+        // shared servers are triggered once
+        let mut servers: Vec<&Arc<HttpServer>> = vec![];
+        for (_, server) in &self.listeners {
+            if !servers.iter().any(|s| Arc::ptr_eq(s, server)) { servers.push(server); }
+        }
+        let shutdowns: Vec<Shutdown> = servers.iter().map(|server| server.shutdown.clone()).collect();
+        let mut running = JoinSet::new();
+        for (listener, server) in self.listeners {
+            running.spawn(listener.serve(server));
+        }
+
+        let mut result = Ok(());
+        let mut first = true;
+        while let Some(res) = running.join_next().await {
+            if first {
+                // One has stopped, stop the others too
+                first = false;
+                for shutdown in &shutdowns {
+                    shutdown.trigger();
+                }
+            }
+            let res = res.unwrap_or_else(|e| Err(io::Error::other(e)));
+            if result.is_ok() {
+                result = res;
+            }
+        }
+        result
+    }
+}
+
+impl Default for ListenerGroup {
+    fn default() -> ListenerGroup {
+        ListenerGroup::new()
+    }
+}
+
+/// Starts handling connections on a given [`HttpServer`], without TLS
+///
+/// Use [`HttpListener`] to know the bound address, or to serve an existing socket
+pub async fn serve_tcp(addr: &str, server: impl Into<Arc<HttpServer>>) -> io::Result<()> {
+    HttpListener::bind(addr)?.serve(server).await
+}
+
 /// Builds the tokio runtime
 ///
 /// This function is a simple replacement for `#[tokio::main]` that does not use macros
@@ -234,3 +833,449 @@ pub fn tokio_rt() -> io::Result<tokio::runtime::Runtime> {
         .enable_all()
         .build()
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::core::HttpResult;
+    use crate::reqres::{res, HttpStream};
+    use crate::services::Router;
+
+    #[test]
+    fn ephemeral_port() {
+        tokio_rt().unwrap().block_on(async {
+            let listener = HttpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            assert_ne!(addr.port(), 0);
+
+            let mut server = HttpServer::new();
+            server.header_timeout = None;
+            server.shutdown_timeout = None;
+            let shutdown = server.shutdown.clone();
+            let serving = tokio::spawn(listener.serve(server));
+
+            // accepted before the other one
+            let mut silent = TcpStream::connect(addr).await.unwrap();
+            let mut conn = TcpStream::connect(addr).await.unwrap();
+            conn.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.unwrap();
+            let mut buf = [0; 12];
+            conn.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"HTTP/1.1 200");
+
+            // idle keep-alive connection, and the one that hasn't sent a request, are closed on shutdown
+            shutdown.trigger();
+            tokio::time::timeout(Duration::from_secs(5), serving).await.unwrap().unwrap().unwrap();
+            assert_eq!(silent.read(&mut buf).await.unwrap(), 0);
+            assert!(TcpStream::connect(addr).await.is_err());
+        });
+    }
+
+    /// Responds with the client's address, after the body
+    struct Peer;
+
+    impl HttpService for Peer {
+        async fn request(&self, _route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+            body.read_to_end(&mut vec![]).await?;
+            Ok(res::text(format!("{}:{}", req.addr, req.port)))
+        }
+
+        fn filter(&self, _route: &str, _req: &HttpRequest) -> HttpResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Serves one connection, and returns everything that was sent back
+    async fn exchange(server: HttpServer, request: &[u8]) -> String {
+        let listener = HttpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(listener.serve(server));
+        let mut conn = TcpStream::connect(addr).await.unwrap();
+        conn.write_all(request).await.unwrap();
+        let mut res = vec![];
+        let _ = conn.read_to_end(&mut res).await;
+        String::from_utf8(res).unwrap()
+    }
+
+    /// Responds with a trailer
+    struct Trailers;
+
+    impl HttpService for Trailers {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            let mut res = res::text("hi");
+            res.add_trailer("X-Checksum", "42");
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn trailers() {
+        tokio_rt().unwrap().block_on(async {
+            let server = || {
+                let mut server = HttpServer::new();
+                server.service(Trailers);
+                server
+            };
+            // `TE` is listed in `Connection`, as RFC 9110 requires
+            let res = exchange(server(), b"GET / HTTP/1.1\r\nHost: a\r\nTE: trailers\r\nConnection: TE, close\r\n\r\n").await;
+            assert!(res.contains("Trailer: X-Checksum\r\n"), "{res}");
+            assert!(res.ends_with("\r\n\r\n2\r\nhi\r\n0\r\nX-Checksum: 42\r\n\r\n"), "{res}");
+
+            let res = exchange(server(), b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").await;
+            assert!(res.ends_with("Content-Length: 2\r\n\r\nhi"), "{res}");
+        });
+    }
+
+    /// Responds with 64 MiB, more than the socket buffers can take
+    struct Large;
+
+    impl HttpService for Large {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            Ok(res::bytes(vec![0; 64 << 20]))
+        }
+    }
+
+    #[test]
+    fn timeouts() {
+        tokio_rt().unwrap().block_on(async {
+            let server = |large| {
+                let mut server = HttpServer::new();
+                if large { server.service(Large) } else { server.service(Peer) };
+                server.header_timeout = Some(Duration::from_millis(100));
+                server.body_timeout = Some(Duration::from_millis(100));
+                server.write_timeout = Some(Duration::from_millis(100));
+                server
+            };
+
+            // headers are not finished
+            let res = exchange(server(false), b"GET / HTTP/1.1\r\nHost: a\r\n").await;
+            assert!(res.starts_with("HTTP/1.1 408"), "{res}");
+
+            // body is not finished, the connection is dropped
+            let res = exchange(server(false), b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhi").await;
+            assert_eq!(res, "");
+
+            // client doesn't read the response
+            let listener = HttpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(listener.serve(server(true)));
+            let mut conn = TcpStream::connect(addr).await.unwrap();
+            conn.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let mut received = 0;
+            let mut buf = vec![0; 65536];
+            while let Ok(n @ 1..) = conn.read(&mut buf).await {
+                received += n;
+            }
+            assert!(received < 64 << 20, "{received}");
+        });
+    }
+
+    /// Streams the chunks, from the end of the list
+    struct Chunks(Vec<&'static str>);
+
+    impl HttpStream for Chunks {
+        async fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+            Ok(self.0.pop().map(|chunk| chunk.as_bytes().to_vec()))
+        }
+    }
+
+    struct Streamed;
+
+    impl HttpService for Streamed {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            Ok(res::stream_with(Chunks(vec![" world", "", "hello"])))
+        }
+
+        fn filter(&self, _route: &str, _req: &HttpRequest) -> HttpResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Remembers the logged byte counts
+    struct Sizes(Arc<std::sync::Mutex<Vec<u64>>>);
+
+    impl HttpLogger for Sizes {
+        fn log(&self, _req: &HttpRequest, _res: &HttpResponse, ctx: &HttpLogContext) {
+            self.0.lock().unwrap().push(ctx.bytes_sent);
+        }
+
+        fn err(&self, _req: &HttpRequest, _res: &HttpResponse, _error: &dyn HttpError, ctx: &HttpLogContext) {
+            self.0.lock().unwrap().push(ctx.bytes_sent);
+        }
+    }
+
+    #[test]
+    fn bytes_sent() {
+        tokio_rt().unwrap().block_on(async {
+            let sizes = Arc::default();
+            let mut server = HttpServer::new();
+            server.service(Streamed).logger(Sizes(Arc::clone(&sizes)));
+            let res = exchange(server, b"GET / HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").await;
+            assert!(res.ends_with("0\r\n\r\n"), "{res}");
+            // only the body, without chunk framing
+            assert_eq!(*sizes.lock().unwrap(), [11, 11]);
+        });
+    }
+
+    #[test]
+    fn streams() {
+        tokio_rt().unwrap().block_on(async {
+            let serve = |request: &'static [u8]| async move {
+                let mut server = HttpServer::new();
+                server.service(Streamed);
+                let res = exchange(server, request).await;
+                // IDs are random
+                res.split("\r\n").filter(|line| !line.starts_with("X-Request-Id:")).collect::<Vec<_>>().join("\r\n")
+            };
+            let head = "HTTP/1.1 200 OK\r\nServer: DrakoHTTP\r\n";
+            let chunked = "Content-Type: application/octet-stream\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+
+            // chunked, empty chunk is skipped, and the connection stays open
+            let res = serve(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").await;
+            assert_eq!(res, format!("{head}Connection: keep-alive\r\nKeep-Alive: timeout=15, max=999\r\n{chunked}{head}Connection: close\r\n{chunked}"));
+
+            // HTTP/1.0 can't chunk, body ends when the connection is closed
+            let close_delimited = format!("{head}Connection: close\r\nContent-Type: application/octet-stream\r\n\r\nhello world");
+            assert_eq!(serve(b"GET / HTTP/1.0\r\n\r\n").await, close_delimited);
+            assert_eq!(serve(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await, close_delimited);
+            let res = serve(b"HEAD / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nHEAD / HTTP/1.0\r\n\r\n").await;
+            assert_eq!(res.matches("HTTP/1.1 200 OK").count(), 2, "{res}");
+        });
+    }
+
+    #[test]
+    fn options_asterisk() {
+        tokio_rt().unwrap().block_on(async {
+            let options = b"OPTIONS * HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n";
+            // answered by the service, and the server's headers are added as usual
+            let res = exchange(HttpServer::new(), options).await;
+            assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+            assert!(res.contains("\r\nX-Request-Id: "), "{res}");
+            assert!(res.contains("\r\nAllow: GET\r\n"), "{res}");
+            assert!(res.ends_with("Content-Length: 0\r\n\r\n"), "{res}");
+
+            // router needs a `*` route for it
+            let mut router = Router::new();
+            router.add("/", DefaultService);
+            let mut server = HttpServer::new();
+            server.service(router);
+            assert!(exchange(server, options).await.starts_with("HTTP/1.1 404 "));
+            let mut router = Router::new();
+            router.add("*", DefaultService);
+            let mut server = HttpServer::new();
+            server.service(router);
+            assert!(exchange(server, options).await.starts_with("HTTP/1.1 200 "));
+
+            let mut server = HttpServer::new();
+            server.strict = true;
+            let res = exchange(server, b"GET * HTTP/1.1\r\nHost: a\r\n\r\n").await;
+            assert!(res.starts_with("HTTP/1.1 400 "), "{res}");
+        });
+    }
+
+    #[test]
+    fn keep_alive() {
+        tokio_rt().unwrap().block_on(async {
+            let serve = |keep_alive_timeout: u64, request: &'static [u8]| async move {
+                let mut server = HttpServer::new();
+                server.keep_alive_timeout = Some(Duration::from_millis(keep_alive_timeout));
+                server.max_requests = Some(2);
+                exchange(server, &request.repeat(3)).await
+            };
+
+            // HTTP/1.1 is persistent by default, and the connection is closed after `max_requests`
+            let res = serve(2500, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await;
+            let responses: Vec<&str> = res.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+            assert_eq!(responses.len(), 2);
+            assert!(responses[0].contains("Connection: keep-alive\r\nKeep-Alive: timeout=2, max=1\r\n"), "{res}");
+            assert!(responses[1].contains("Connection: close\r\n") && !responses[1].contains("Keep-Alive"), "{res}");
+
+            // timeout is not advertised as 0
+            let res = serve(500, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await;
+            assert!(res.contains("Connection: keep-alive\r\nKeep-Alive: max=1\r\n"), "{res}");
+
+            // HTTP/1.0 has to ask for it
+            let res = serve(2500, b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await;
+            assert_eq!(res.matches("HTTP/1.1 200 OK").count(), 2);
+            assert!(res.contains("Connection: keep-alive\r\nKeep-Alive: timeout=2, max=1\r\n"), "{res}");
+            let res = serve(2500, b"GET / HTTP/1.0\r\n\r\n").await;
+            assert_eq!(res.matches("HTTP/1.1 200 OK").count(), 1);
+            assert!(res.contains("Connection: close\r\n"), "{res}");
+        });
+    }
+
+    #[test]
+    fn proxy_protocol() {
+        tokio_rt().unwrap().block_on(async {
+            let listener = HttpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let mut server = HttpServer::new();
+            server.service(Peer);
+            server.proxy_protocol.push("127.0.0.0/8".parse().unwrap());
+            let shutdown = server.shutdown.clone();
+            let serving = tokio::spawn(listener.serve(server));
+
+            let request = |header: &'static str| async move {
+                let mut conn = TcpStream::connect(addr).await.unwrap();
+                conn.write_all(header.as_bytes()).await.unwrap();
+                conn.write_all(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").await.unwrap();
+                let mut res = String::new();
+                let _ = conn.read_to_string(&mut res).await;
+                res
+            };
+            assert!(request("PROXY TCP4 192.0.2.1 127.0.0.1 51000 80\r\n").await.ends_with("\r\n\r\n192.0.2.1:51000"));
+            // address of the proxy itself
+            assert!(request("PROXY UNKNOWN\r\n").await.contains("\r\n\r\n127.0.0.1:"));
+            // header is required from trusted proxies
+            assert_eq!(request("").await, "");
+
+            shutdown.trigger();
+            serving.await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn limits() {
+        tokio_rt().unwrap().block_on(async {
+            let start = |max_connections, max_in_flight| {
+                let listener = HttpListener::bind("127.0.0.1:0").unwrap();
+                let addr = listener.local_addr().unwrap();
+                let mut server = HttpServer::new();
+                server.service(Peer);
+                server.max_connections = max_connections;
+                server.max_in_flight = max_in_flight;
+                tokio::spawn(listener.serve(server));
+                addr
+            };
+            let status = async |conn: &mut TcpStream| {
+                let mut buf = [0; 12];
+                conn.read_exact(&mut buf).await.unwrap();
+                buf
+            };
+
+            // second request waits for the body of the first one
+            let addr = start(None, Some(1));
+            let mut first = TcpStream::connect(addr).await.unwrap();
+            first.write_all(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\n").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let mut second = TcpStream::connect(addr).await.unwrap();
+            second.write_all(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").await.unwrap();
+            let mut res = String::new();
+            second.read_to_string(&mut res).await.unwrap();
+            assert!(res.starts_with("HTTP/1.1 503"));
+            assert!(res.contains("Retry-After: 5\r\n"));
+            let mut server = HttpServer::new();
+            server.retry_after = Duration::from_millis(1500);
+            let res = server.overloaded(&HttpRequest::default());
+            assert!(res.headers.iter().any(|h| h.name == "Retry-After" && h.value == "2"));
+            first.write_all(b"hi").await.unwrap();
+            assert_eq!(&status(&mut first).await, b"HTTP/1.1 200");
+
+            // second connection is accepted once the first one is closed
+            let addr = start(Some(1), None);
+            let mut first = TcpStream::connect(addr).await.unwrap();
+            first.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.unwrap();
+            assert_eq!(&status(&mut first).await, b"HTTP/1.1 200");
+            let mut second = TcpStream::connect(addr).await.unwrap();
+            second.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.unwrap();
+            assert!(tokio::time::timeout(Duration::from_millis(100), status(&mut second)).await.is_err());
+            drop(first);
+            assert_eq!(&status(&mut second).await, b"HTTP/1.1 200");
+        });
+    }
+
+    #[test]
+    fn request_id() {
+        tokio_rt().unwrap().block_on(async {
+            let listener = HttpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let mut server = HttpServer::new();
+            server.trust_request_id(|_req, id| id.starts_with("lb-"));
+            tokio::spawn(listener.serve(server));
+
+            let request_id = async |id: &str| {
+                let mut conn = TcpStream::connect(addr).await.unwrap();
+                let req = format!("GET / HTTP/1.1\r\nHost: a\r\nX-Request-Id: {id}\r\nConnection: close\r\n\r\n");
+                conn.write_all(req.as_bytes()).await.unwrap();
+                let mut res = String::new();
+                conn.read_to_string(&mut res).await.unwrap();
+                let line = res.lines().find_map(|l| l.strip_prefix("X-Request-Id: ")).unwrap();
+                line.to_string()
+            };
+            assert_eq!(request_id("lb-42").await, "lb-42");
+            let generated = request_id("other").await;
+            assert_eq!(generated.len(), 32);
+            assert_ne!(request_id("lb-<42>").await, "lb-<42>");
+        });
+    }
+
+    #[test]
+    fn group() {
+        tokio_rt().unwrap().block_on(async {
+            let public = HttpServer::new();
+            let mut admin = HttpServer::new();
+            admin.name = "Admin".to_string();
+            let shutdown = admin.shutdown.clone();
+
+            let first = HttpListener::bind("127.0.0.1:0").unwrap();
+            let second = HttpListener::bind("127.0.0.1:0").unwrap();
+            let (first_addr, second_addr) = (first.local_addr().unwrap(), second.local_addr().unwrap());
+            let mut group = ListenerGroup::new();
+            group.add(first, public).add(second, admin);
+            let serving = tokio::spawn(group.serve());
+
+            let mut conn = TcpStream::connect(second_addr).await.unwrap();
+            conn.write_all(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n").await.unwrap();
+            let mut res = String::new();
+            conn.read_to_string(&mut res).await.unwrap();
+            assert!(res.contains("Server: Admin\r\n"));
+
+            // admin shutdown stops the public listener too
+            shutdown.trigger();
+            serving.await.unwrap().unwrap();
+            assert!(TcpStream::connect(first_addr).await.is_err());
+        });
+    }
+
+    /// Responds after a while
+    struct Slow;
+
+    impl HttpService for Slow {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok(res::text("done"))
+        }
+    }
+
+    #[test]
+    fn group_drain() {
+        tokio_rt().unwrap().block_on(async {
+            let mut slow = HttpServer::new();
+            slow.service(Slow);
+            let other = HttpServer::new();
+            let shutdown = other.shutdown.clone();
+
+            let first = HttpListener::bind("127.0.0.1:0").unwrap();
+            let addr = first.local_addr().unwrap();
+            let mut group = ListenerGroup::new();
+            group.add(first, slow).add(HttpListener::bind("127.0.0.1:0").unwrap(), other);
+            let serving = tokio::spawn(group.serve());
+
+            let mut conn = TcpStream::connect(addr).await.unwrap();
+            conn.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            // other listener has nothing to drain, but the group waits for the request in flight
+            shutdown.trigger();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(!serving.is_finished());
+            let mut res = String::new();
+            conn.read_to_string(&mut res).await.unwrap();
+            assert!(res.starts_with("HTTP/1.1 200 OK\r\n") && res.contains("Connection: close\r\n"), "{res}");
+            assert!(res.ends_with("done"));
+            serving.await.unwrap().unwrap();
+        });
+    }
+}
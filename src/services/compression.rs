@@ -0,0 +1,453 @@
+use std::io::{self, Write};
+
+use flate2::Compression as Level;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::core::{HttpLayer, HttpServiceRaw, HttpResult, HttpRead};
+use crate::h1;
+use crate::reqres::{HttpRequest, HttpResponse, HttpBody, HttpStream, HttpMethod, StatusCode};
+use crate::reqres::body::BytesStream;
+
+/// Bodies over this size are compressed on the blocking thread pool
+const BLOCKING_SIZE: usize = 65536;
+/// Size of file chunks that are compressed at once
+const FILE_CHUNK: usize = 16384;
+
+/// Content coding, in `Accept-Encoding` and `Content-Encoding`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    /// Zlib format, despite the name
+    Deflate,
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+        }
+    }
+}
+
+/// Layer that compresses responses, see [`HttpLayer`]
+///
+/// Compresses byte and file bodies of text-like types (see [`is_compressible`]),
+/// with the encoding that the client prefers in `Accept-Encoding`.
+/// Partial content, streams and responses with `Content-Encoding` or `Cache-Control: no-transform` are sent as is.
+/// Compressed files are streamed, so they lose `Content-Length` and range support
+///
+/// ```
+/// # use dhttp::core::HttpServiceExt;
+/// # use dhttp::services::{Compression, FilesService};
+/// let service = FilesService::new("public").layer(Compression::new());
+/// ```
+pub struct Compression {
+    /// Encodings to use, the first one wins when the client likes several of them equally
+    pub encodings: Vec<Encoding>,
+    /// Smaller bodies are not worth it
+    pub min_size: u64,
+    /// Gzip and deflate level, from 0 to 9
+    pub level: u32,
+    /// Brotli quality, from 0 to 11. It gets very slow at the top
+    #[cfg(feature = "brotli")]
+    pub brotli_quality: u32,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression {
+            #[cfg(feature = "brotli")]
+            encodings: vec![Encoding::Brotli, Encoding::Gzip, Encoding::Deflate],
+            #[cfg(not(feature = "brotli"))]
+            encodings: vec![Encoding::Gzip, Encoding::Deflate],
+            min_size: 1024,
+            level: 6,
+            #[cfg(feature = "brotli")]
+            brotli_quality: 4,
+        }
+    }
+
+    /// Picks the encoding by q-values in `Accept-Encoding`, `None` means to send it as is
+    pub fn negotiate(&self, req: &HttpRequest) -> Option<Encoding> {
+        let mut best: Option<(Encoding, f32)> = None;
+        for &encoding in &self.encodings {
+            let q = accepted(req, encoding.as_str());
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn encoder(&self, encoding: Encoding) -> Encoder {
+        match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(vec![], Level::new(self.level))),
+            Encoding::Deflate => Encoder::Deflate(ZlibEncoder::new(vec![], Level::new(self.level))),
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(vec![], 4096, self.brotli_quality, 22))),
+        }
+    }
+
+    /// Compresses the body if it's worth it, and marks the response as varying by `Accept-Encoding`
+    async fn compress(&self, req: &HttpRequest, res: &mut HttpResponse) -> io::Result<()> {
+        if res.code.0 == StatusCode::PARTIAL_CONTENT.0 || !is_compressible(&res.content_type) { return Ok(()); }
+        let len = match &res.body {
+            HttpBody::Bytes(bytes) => bytes.len() as u64,
+            HttpBody::File { len, .. } => *len,
+            _ => return Ok(()),
+        };
+        if header_values(res, "Content-Encoding").next().is_some() || header_values(res, "Content-Range").next().is_some() { return Ok(()); }
+        if header_values(res, "Cache-Control").any(|v| has_token(v, "no-transform")) { return Ok(()); }
+        if len < self.min_size { return Ok(()); }
+
+        // caches must not give compressed responses to everyone
+        if !header_values(res, "Vary").any(|v| has_token(v, "Accept-Encoding") || has_token(v, "*")) {
+            res.add_header("Vary", "Accept-Encoding");
+        }
+        // compressed files are streamed, and HTTP/1.0 would lose keep-alive for a close-delimited body
+        if matches!(res.body, HttpBody::File { .. }) && !h1::can_chunk(req) { return Ok(()); }
+        let Some(encoding) = self.negotiate(req) else { return Ok(()) };
+        let mut encoder = self.encoder(encoding);
+
+        match std::mem::replace(&mut res.body, HttpBody::Bytes(vec![])) {
+            HttpBody::Bytes(bytes) => {
+                let large = bytes.len() > BLOCKING_SIZE;
+                let compress = move || encoder.write_all(&bytes).and_then(|()| encoder.finish()).map(|out| (out, bytes));
+                let (out, bytes) = if large {
+                    tokio::task::spawn_blocking(compress).await.map_err(io::Error::other)??
+                } else {
+                    compress()?
+                };
+                // incompressible after all
+                if out.len() >= bytes.len() {
+                    res.body = HttpBody::Bytes(bytes);
+                    return Ok(());
+                }
+                res.body = HttpBody::Bytes(out);
+            }
+            HttpBody::File { file, len } => {
+                // HEAD only gets the headers, the file is not read
+                res.body = if req.method == HttpMethod::Head {
+                    HttpBody::Stream(Box::new(BytesStream(None)))
+                } else {
+                    HttpBody::Stream(Box::new(CompressStream { reader: file.take(len), encoder: Some(encoder) }))
+                };
+                res.headers.retain(|h| !h.name.eq_ignore_ascii_case("Accept-Ranges"));
+            }
+            _ => unreachable!(),
+        }
+
+        res.add_header("Content-Encoding", encoding.as_str());
+        // strong ETag belongs to the uncompressed bytes
+        for h in &mut res.headers {
+            if h.name.eq_ignore_ascii_case("ETag") && h.value.starts_with('"') {
+                h.value.insert_str(0, "W/");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl HttpLayer for Compression {
+    async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: &dyn HttpServiceRaw) -> HttpResult {
+        let mut res = next.request_raw(route, req, body).await?;
+        self.compress(req, &mut res).await?;
+        Ok(res)
+    }
+}
+
+/// Is it worth compressing this `Content-Type`
+///
+/// Text, JSON, XML, JavaScript, SVG and uncompressed fonts are. Images, video, audio and archives
+/// are compressed already
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    let Some((kind, subtype)) = mime.split_once('/') else { return false };
+    // event streams must reach the client right away
+    if kind == "text" { return subtype != "event-stream"; }
+    if subtype.ends_with("+json") || subtype.ends_with("+xml") { return true; }
+    matches!(
+        mime.as_str(),
+        "application/json" | "application/javascript" | "application/ecmascript" | "application/x-javascript"
+        | "application/xml" | "application/wasm" | "application/x-ndjson" | "application/vnd.ms-fontobject"
+        | "image/x-icon" | "image/vnd.microsoft.icon" | "image/bmp" | "font/ttf" | "font/otf"
+    )
+}
+
+fn header_values<'a>(res: &'a HttpResponse, name: &'a str) -> impl Iterator<Item = &'a str> {
+    res.headers.iter().filter(move |h| h.name.eq_ignore_ascii_case(name)).map(|h| h.value.as_str())
+}
+
+/// Is `token` in a comma-separated list of tokens with parameters
+fn has_token(list: &str, token: &str) -> bool {
+    list.split(',').any(|t| t.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case(token))
+}
+
+/// Q-value of an encoding in `Accept-Encoding`, 0 if not accepted
+///
+/// Exact names win over `*`, invalid q-values are ignored
+fn accepted(req: &HttpRequest, encoding: &str) -> f32 {
+    let mut exact = None;
+    let mut any = None;
+    for item in req.get_headers("Accept-Encoding").flat_map(|v| v.split(',')) {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let mut q = Some(1.0);
+        for param in params {
+            if let Some((key, value)) = param.split_once('=') && key.trim().eq_ignore_ascii_case("q") {
+                q = parse_q(value.trim());
+            }
+        }
+        let Some(q) = q else { continue };
+        // x-gzip is an old alias of gzip
+        if name.eq_ignore_ascii_case(encoding) || (encoding == "gzip" && name.eq_ignore_ascii_case("x-gzip")) {
+            exact = Some(q);
+        } else if name == "*" {
+            any = Some(q);
+        }
+    }
+    exact.or(any).unwrap_or(0.0)
+}
+
+/// `0`, `1`, and up to 3 digits after the point
+fn parse_q(q: &str) -> Option<f32> {
+    let (int, frac) = q.split_once('.').unwrap_or((q, ""));
+    if !matches!(int, "0" | "1") || frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) { return None; }
+    if q.ends_with('.') { return None; }
+    let q: f32 = q.parse().ok()?;
+    (q <= 1.0).then_some(q)
+}
+
+/// Compressor that writes to memory
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(e) => e.write_all(buf),
+            Encoder::Deflate(e) => e.write_all(buf),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(e) => e.write_all(buf),
+        }
+    }
+
+    /// Takes out what is compressed so far
+    fn take(&mut self) -> Vec<u8> {
+        std::mem::take(match self {
+            Encoder::Gzip(e) => e.get_mut(),
+            Encoder::Deflate(e) => e.get_mut(),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(e) => e.get_mut(),
+        })
+    }
+
+    /// Ends the stream, and returns the rest
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(e) => e.finish(),
+            Encoder::Deflate(e) => e.finish(),
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(e) => Ok(e.into_inner()),
+        }
+    }
+}
+
+/// Compresses a reader chunk by chunk
+struct CompressStream<R> {
+    reader: R,
+    /// `None` once finished
+    encoder: Option<Encoder>,
+}
+
+impl<R: AsyncRead + Unpin + Send> HttpStream for CompressStream<R> {
+    async fn next(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut chunk = vec![0; FILE_CHUNK];
+        // encoder may keep small inputs, so read until it gives something
+        while let Some(encoder) = &mut self.encoder {
+            let len = self.reader.read(&mut chunk).await?;
+            if len == 0 {
+                let encoder = self.encoder.take().unwrap();
+                return Ok(Some(encoder.finish()?));
+            }
+            encoder.write_all(&chunk[..len])?;
+            let out = encoder.take();
+            if !out.is_empty() { return Ok(Some(out)); }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    use super::*;
+    use crate::core::HttpServiceExt;
+    use crate::reqres::{res, HttpHeader, HttpVersion};
+
+    fn request(accept: &str) -> HttpRequest {
+        let headers = vec![HttpHeader { name: "Accept-Encoding".to_string(), value: accept.to_string() }];
+        HttpRequest { headers, version: HttpVersion { major: 1, minor: 1 }, ..HttpRequest::default() }
+    }
+
+    fn header<'a>(res: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        res.headers.iter().find(|h| h.name == name).map(|h| h.value.as_str())
+    }
+
+    #[test]
+    fn negotiate() {
+        let c = Compression { encodings: vec![Encoding::Gzip, Encoding::Deflate], ..Compression::new() };
+        let negotiated = |accept| c.negotiate(&request(accept));
+        assert_eq!(negotiated("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(negotiated("deflate, gzip;q=0.5"), Some(Encoding::Deflate));
+        assert_eq!(negotiated("GZIP;Q=0.2, *;q=0.4"), Some(Encoding::Deflate));
+        assert_eq!(negotiated("*"), Some(Encoding::Gzip));
+        assert_eq!(negotiated("x-gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiated("gzip;q=0, deflate;q=0.000"), None);
+        assert_eq!(negotiated("*, gzip;q=0, deflate;q=0"), None);
+        assert_eq!(negotiated("identity"), None);
+        assert_eq!(negotiated(""), None);
+        // invalid q-values are skipped
+        assert_eq!(negotiated("gzip;q=2, deflate;q=.5"), None);
+        assert_eq!(negotiated("gzip;q=0.5555, deflate;q=1.0"), Some(Encoding::Deflate));
+    }
+
+    #[test]
+    fn compress() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let text = "hello compressed world\n".repeat(100);
+        let service = crate::services::DefaultService.layer(Compression::new());
+        let run = |res: HttpResponse, accept| {
+            let req = request(accept);
+            let mut res = res;
+            rt.block_on(Compression::new().compress(&req, &mut res)).unwrap();
+            res
+        };
+        let bytes = |res: &HttpResponse| match &res.body {
+            HttpBody::Bytes(b) => b.clone(),
+            body => panic!("{body:?}"),
+        };
+
+        let res = run(res::text(text.clone()), "gzip");
+        assert_eq!(header(&res, "Content-Encoding"), Some("gzip"));
+        assert_eq!(header(&res, "Vary"), Some("Accept-Encoding"));
+        let mut out = String::new();
+        GzDecoder::new(&bytes(&res)[..]).read_to_string(&mut out).unwrap();
+        assert_eq!(out, text);
+
+        let res = run(res::json(text.clone()), "deflate");
+        let mut out = String::new();
+        ZlibDecoder::new(&bytes(&res)[..]).read_to_string(&mut out).unwrap();
+        assert_eq!(out, text);
+
+        #[cfg(feature = "brotli")]
+        {
+            let res = run(res::html(text.clone()), "gzip;q=0.9, br");
+            assert_eq!(header(&res, "Content-Encoding"), Some("br"));
+            let mut out = String::new();
+            brotli::Decompressor::new(&bytes(&res)[..], 4096).read_to_string(&mut out).unwrap();
+            assert_eq!(out, text);
+        }
+
+        // not accepted, but still varies
+        let res = run(res::text(text.clone()), "identity");
+        assert_eq!(header(&res, "Content-Encoding"), None);
+        assert_eq!(header(&res, "Vary"), Some("Accept-Encoding"));
+        assert_eq!(bytes(&res), text.as_bytes());
+
+        // too small, already compressed, or partial
+        let res = run(res::text("small"), "gzip");
+        assert_eq!(header(&res, "Content-Encoding"), None);
+        let res = run(HttpResponse::with_type("image/png", text.clone()), "gzip");
+        assert_eq!(header(&res, "Content-Encoding"), None);
+        let mut partial = res::text(text.clone());
+        partial.code = StatusCode::PARTIAL_CONTENT;
+        assert_eq!(header(&run(partial, "gzip"), "Content-Encoding"), None);
+
+        // through the layer, small default response is left alone
+        let req = request("gzip");
+        let res = rt.block_on(service.request_raw("/", &req, &mut tokio::io::empty())).unwrap();
+        assert_eq!(header(&res, "Content-Encoding"), None);
+    }
+
+    #[test]
+    fn file() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let text = "line of a compressed file\n".repeat(5000);
+        let path = std::env::temp_dir().join(format!("dhttp-compression-{}.txt", std::process::id()));
+        std::fs::write(&path, &text).unwrap();
+
+        let out = rt.block_on(async {
+            let file = tokio::fs::File::open(&path).await.unwrap();
+            let len = text.len() as u64;
+            let mut res = HttpResponse::with_type("text/plain", HttpBody::File { file, len });
+            res.add_header("Accept-Ranges", "bytes");
+            res.add_header("ETag", "\"abc\"");
+            Compression::new().compress(&request("gzip"), &mut res).await.unwrap();
+            assert_eq!(header(&res, "Accept-Ranges"), None);
+            assert_eq!(header(&res, "ETag"), Some("W/\"abc\""));
+            let HttpBody::Stream(mut stream) = res.body else { panic!("not a stream") };
+            let mut out = vec![];
+            while let Some(chunk) = stream.next_raw().await.unwrap() {
+                out.extend_from_slice(&chunk);
+            }
+            out
+        });
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(out.len() < text.len() / 10);
+        let mut decoded = String::new();
+        GzDecoder::new(&out[..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[test]
+    fn file_head() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let text = "line of a compressed file\n".repeat(100);
+        let path = std::env::temp_dir().join(format!("dhttp-compression-head-{}.txt", std::process::id()));
+        std::fs::write(&path, &text).unwrap();
+
+        rt.block_on(async {
+            let (path, len) = (&path, text.len() as u64);
+            let compress = |req: HttpRequest| async move {
+                let file = tokio::fs::File::open(path).await.unwrap();
+                let mut res = HttpResponse::with_type("text/plain", HttpBody::File { file, len });
+                Compression::new().compress(&req, &mut res).await.unwrap();
+                res
+            };
+
+            // same headers as GET, but nothing is read
+            let res = compress(HttpRequest { method: HttpMethod::Head, ..request("gzip") }).await;
+            assert_eq!(header(&res, "Content-Encoding"), Some("gzip"));
+            let HttpBody::Stream(mut stream) = res.body else { panic!("not a stream") };
+            assert!(stream.next_raw().await.unwrap().is_none());
+
+            // HTTP/1.0 keeps the file and its Content-Length
+            let res = compress(HttpRequest { version: HttpVersion { major: 1, minor: 0 }, ..request("gzip") }).await;
+            assert_eq!(header(&res, "Content-Encoding"), None);
+            assert_eq!(header(&res, "Vary"), Some("Accept-Encoding"));
+            assert!(matches!(res.body, HttpBody::File { .. }));
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,11 +1,27 @@
 use crate::core::{HttpService, HttpResult, HttpRead};
-use crate::reqres::{res, HttpRequest};
+use crate::reqres::{res, HttpRequest, HttpResponse, HttpMethod, StatusCode};
 
 /// Default service which only returns `"drakohttp is here!"`
+///
+/// Also answers `OPTIONS *` with the methods it allows
 pub struct DefaultService;
 
 impl HttpService for DefaultService {
-    async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+    async fn request(&self, route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+        if route == "*" {
+            let mut res = HttpResponse::new();
+            res.add_header("Allow", "GET");
+            return Ok(res);
+        }
         Ok(res::text("drakohttp is here!\n"))
     }
+
+    fn filter(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        // `OPTIONS *` asks about the server itself
+        let method = if route == "*" { HttpMethod::Options } else { HttpMethod::Get };
+        if route != "/" && route != "*" { return Err(StatusCode::NOT_FOUND.into()); }
+        if req.method != method { return Err(StatusCode::METHOD_NOT_ALLOWED.into()); }
+        if req.len != Some(0) { return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into()); }
+        Ok(())
+    }
 }
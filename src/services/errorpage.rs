@@ -1,14 +1,16 @@
 use crate::core::{HttpError, HttpErrorHandler};
 use crate::reqres::{res, HttpRequest, HttpResponse, StatusCode};
 
-fn error_page(code: u16, code_desc: &str, desc: &str, name: &str) -> String {
+fn error_page(code: u16, code_desc: &str, desc: &str, name: &str, id: &str) -> String {
+// ID is validated, it's safe in HTML
+let id = if id.is_empty() { String::new() } else { format!("<br><small>Request ID: {id}</small>") };
 format!(r#"<!doctype html>
 <html><title>{code} {code_desc}</title><meta name="viewport" content="width=device-width"><style>*{{font-family:sans-serif;color:#0e1219;background:#f9f9f9}}@media(prefers-color-scheme:dark){{*{{color:#47d8bb;background:#0e1219}}}}h1{{margin:0;}}div{{position:fixed;top:50%;left:50%;transform:translate(-50%,-50%);padding:8px;border:4px solid;border-color:#0e1219}}@media(prefers-color-scheme:dark){{div{{border-color:#47d8bb}}}}</style><div>
 
 <h1>    {code} {code_desc}    </h1>
         {desc}
 <hr><!--------------------------->
-<center>    {name}    </center>
+<center>    {name}{id}    </center>
 
 </div></html>
 "#)
@@ -20,13 +22,17 @@ pub struct ErrorPageHandler {
 }
 
 impl HttpErrorHandler for ErrorPageHandler {
-    fn error(&self, _req: &HttpRequest, error: &dyn HttpError) -> HttpResponse {
+    fn error(&self, req: &HttpRequest, error: &dyn HttpError) -> HttpResponse {
         let code = error.status_code();
         let desc = error.http_description();
-        res::html(error_page(code.0, code.as_str(), &desc, &self.name))
+        res::html(error_page(code.0, code.as_str(), &desc, &self.name, &req.id))
     }
 
     fn plain_code(&self, code: StatusCode) -> HttpResponse {
-        res::html(error_page(code.0, code.as_str(), "", &self.name))
+        res::html(error_page(code.0, code.as_str(), "", &self.name, ""))
+    }
+
+    fn plain_code_for(&self, req: &HttpRequest, code: StatusCode) -> HttpResponse {
+        res::html(error_page(code.0, code.as_str(), "", &self.name, &req.id))
     }
 }
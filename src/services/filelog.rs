@@ -0,0 +1,431 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::task::AbortHandle;
+
+use crate::core::{HttpLogger, HttpLogContext, HttpError};
+use crate::reqres::{HttpRequest, HttpResponse};
+use crate::services::LogFormat;
+
+/// Writer wakes up this often to flush, rotate and reopen
+const TICK: Duration = Duration::from_secs(1);
+
+/// Options of [`FileLogger`]
+#[derive(Debug, Clone)]
+pub struct FileLogOptions {
+    pub format: LogFormat,
+    /// Rotate when the file grows over this size
+    pub max_size: Option<u64>,
+    /// Rotate at every multiple of this interval since the Unix epoch
+    ///
+    /// One day rotates at midnight UTC, one hour at the start of every hour
+    pub rotate_every: Option<Duration>,
+    /// Number of rotated files to keep, `access.log.1` is the newest one
+    pub keep: usize,
+    /// How many lines can wait for the writer, new ones are dropped when it's full
+    pub buffer: usize,
+}
+
+impl FileLogOptions {
+    pub fn new() -> FileLogOptions {
+        FileLogOptions { format: LogFormat::Combined, max_size: None, rotate_every: None, keep: 7, buffer: 8192 }
+    }
+}
+
+impl Default for FileLogOptions {
+    fn default() -> FileLogOptions {
+        FileLogOptions::new()
+    }
+}
+
+/// Sent to the writer thread
+enum Message {
+    Line(String),
+    /// Answered once everything before it is written out
+    Sync(SyncSender<()>),
+}
+
+/// State shared with the writer thread
+#[derive(Default)]
+struct Shared {
+    dropped: AtomicU64,
+    failed: AtomicU64,
+    reopen: AtomicBool,
+}
+
+/// Logger that writes to a file on a background thread
+///
+/// Requests never wait for the disk: lines are queued, and dropped if the queue is full (see [`FileLogger::dropped`]).
+/// The file is rotated by size or time, and reopened on demand, like after logrotate moves it away.
+///
+/// The writer is a thread of its own, not a tokio task. File writes block, and a loop that lives as long as
+/// the logger would hold one of the runtime's blocking threads for good. This way it also works without a runtime,
+/// and dropping the logger waits until the queue is written out. The task of [`FileLogger::reopen_on_sighup`]
+/// only asks the writer to reopen, and it's stopped on drop too.
+///
+///
+/// ```no_run
+/// # use dhttp::server::HttpServer;
+/// # use dhttp::services::{FileLogger, FileLogOptions};
+/// # async fn f(mut server: HttpServer) -> std::io::Result<()> {
+/// let logger = FileLogger::open("/var/log/dhttp/access.log", &FileLogOptions::new())?;
+/// logger.reopen_on_sighup()?;
+/// server.logger(logger);
+/// # Ok(())
+/// # }
+/// ```
+pub struct FileLogger {
+    format: LogFormat,
+    sender: Option<SyncSender<Message>>,
+    shared: Arc<Shared>,
+    writer: Option<JoinHandle<()>>,
+    /// Task of [`FileLogger::reopen_on_sighup`], stopped with the logger
+    sighup: Mutex<Option<AbortHandle>>,
+}
+
+impl FileLogger {
+    /// Opens the file for appending and starts the writer
+    pub fn open(path: impl AsRef<Path>, options: &FileLogOptions) -> io::Result<FileLogger> {
+        let path = path.as_ref().to_path_buf();
+        let output = Output::open(path, options)?;
+        let (sender, receiver) = mpsc::sync_channel(options.buffer);
+        let shared = Arc::new(Shared::default());
+        let shared2 = Arc::clone(&shared);
+        let writer = std::thread::Builder::new()
+            .name("dhttp-log".to_string())
+            .spawn(move || output.run(receiver, &shared2))?;
+        Ok(FileLogger { format: options.format, sender: Some(sender), shared, writer: Some(writer), sighup: Mutex::new(None) })
+    }
+
+    /// Number of lines dropped because the writer could not keep up
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of lines lost because the file could not be written, like when the disk is full
+    pub fn failed(&self) -> u64 {
+        self.shared.failed.load(Ordering::Relaxed)
+    }
+
+    /// Reopens the file by its path within a second
+    pub fn reopen(&self) {
+        self.shared.reopen.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits until the queued lines are written out, and the file is rotated or reopened if it's due
+    ///
+    /// Blocks the thread, so don't call it from async code
+    pub fn sync(&self) {
+        let Some(sender) = &self.sender else { return };
+        let (done, wait) = mpsc::sync_channel(1);
+        if sender.send(Message::Sync(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+
+    /// Reopens the file on `SIGHUP`, like logrotate expects. Must be called inside the tokio runtime
+    #[cfg(unix)]
+    pub fn reopen_on_sighup(&self) -> io::Result<()> {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut hup = signal(SignalKind::hangup())?;
+        let shared = Arc::clone(&self.shared);
+        let task = tokio::spawn(async move {
+            while hup.recv().await.is_some() {
+                shared.reopen.store(true, Ordering::Relaxed);
+            }
+        });
+        if let Some(old) = self.sighup.lock().unwrap().replace(task.abort_handle()) {
+            old.abort();
+        }
+        Ok(())
+    }
+
+    fn send(&self, line: String) {
+        let Some(sender) = &self.sender else { return };
+        // queue is full, or the writer has stopped
+        if sender.try_send(Message::Line(line)).is_err() {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl HttpLogger for FileLogger {
+    fn log(&self, req: &HttpRequest, res: &HttpResponse, ctx: &HttpLogContext) {
+        self.send(self.format.format(req, res, ctx, None));
+    }
+
+    fn err(&self, req: &HttpRequest, res: &HttpResponse, error: &dyn HttpError, ctx: &HttpLogContext) {
+        self.send(self.format.format(req, res, ctx, Some(error)));
+    }
+
+    fn accept_err(&self, error: &io::Error) {
+        self.send(self.format.format_accept_err(SystemTime::now(), error));
+    }
+}
+
+impl Drop for FileLogger {
+    /// Writes out the queued lines
+    fn drop(&mut self) {
+        if let Some(task) = self.sighup.get_mut().unwrap().take() {
+            task.abort();
+        }
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// The file, owned by the writer thread
+struct Output {
+    path: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    max_size: Option<u64>,
+    rotate_every: Option<Duration>,
+    /// When the time-based rotation is due
+    next_rotation: Option<SystemTime>,
+    keep: usize,
+    /// Errors are shown once until writing works again
+    failing: bool,
+    /// Lines lost since it started failing
+    lost: u64,
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Next multiple of `interval` since the epoch, after `now`
+fn next_boundary(now: SystemTime, interval: Duration) -> SystemTime {
+    let interval = interval.as_secs().max(1);
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    UNIX_EPOCH + Duration::from_secs((now / interval + 1) * interval)
+}
+
+/// `access.log.N`
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+impl Output {
+    fn open(path: PathBuf, options: &FileLogOptions) -> io::Result<Output> {
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Output {
+            path,
+            file: BufWriter::new(file),
+            size,
+            max_size: options.max_size,
+            rotate_every: options.rotate_every,
+            next_rotation: options.rotate_every.map(|every| next_boundary(SystemTime::now(), every)),
+            keep: options.keep,
+            failing: false,
+            lost: 0,
+        })
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let file = open_append(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = BufWriter::new(file);
+        Ok(())
+    }
+
+    /// Shifts `access.log.N` to `access.log.N+1`, the oldest one is removed
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let ignore_missing = |result: io::Result<()>| match result {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        };
+        if self.keep == 0 {
+            ignore_missing(std::fs::remove_file(&self.path))?;
+        } else {
+            for n in (1..self.keep).rev() {
+                ignore_missing(std::fs::rename(numbered(&self.path, n), numbered(&self.path, n + 1)))?;
+            }
+            ignore_missing(std::fs::rename(&self.path, numbered(&self.path, 1)))?;
+        }
+        self.reopen()
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Rotates or reopens the file if needed
+    fn maintain(&mut self, shared: &Shared) -> io::Result<()> {
+        if shared.reopen.swap(false, Ordering::Relaxed) {
+            self.reopen()?;
+        }
+        let now = SystemTime::now();
+        if let (Some(every), Some(next)) = (self.rotate_every, self.next_rotation) && now >= next {
+            self.next_rotation = Some(next_boundary(now, every));
+            if self.size > 0 { self.rotate()?; }
+        }
+        if self.max_size.is_some_and(|max| self.size >= max) {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Counts the `lines` of a failed batch. Shown only when writing starts failing and when it works again
+    fn report(&mut self, result: io::Result<()>, lines: u64, shared: &Shared) {
+        match result {
+            Ok(()) if self.failing => {
+                eprintln!("DrakoHTTP: writing to {} works again, {} lines were lost", self.path.display(), self.lost);
+                self.failing = false;
+                self.lost = 0;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                shared.failed.fetch_add(lines, Ordering::Relaxed);
+                self.lost += lines;
+                if !self.failing {
+                    eprintln!("DrakoHTTP critical error: could not write to {}: {e}", self.path.display());
+                    self.failing = true;
+                }
+            }
+        }
+    }
+
+    fn run(mut self, receiver: Receiver<Message>, shared: &Shared) {
+        loop {
+            let mut result = Ok(());
+            let mut lines = 0;
+            let mut synced = None;
+            match receiver.recv_timeout(TICK) {
+                Ok(message) => {
+                    // write out everything that is queued at once
+                    let mut next = Some(message);
+                    while let Some(message) = next {
+                        match message {
+                            Message::Line(line) => {
+                                result = result.and_then(|()| self.write(&line));
+                                lines += 1;
+                            }
+                            Message::Sync(done) => {
+                                synced = Some(done);
+                                break;
+                            }
+                        }
+                        if self.max_size.is_some_and(|max| self.size >= max) {
+                            result = result.and_then(|()| self.rotate());
+                        }
+                        next = receiver.try_recv().ok();
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    let result = self.file.flush();
+                    self.report(result, 0, shared);
+                    return;
+                }
+            }
+            let result = result.and_then(|()| self.maintain(shared)).and_then(|()| self.file.flush());
+            self.report(result, lines, shared);
+            if let Some(done) = synced {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reqres::HttpVersion;
+
+    fn log_request(logger: &FileLogger, route: &str) {
+        let req = HttpRequest { route: route.to_string(), version: HttpVersion { major: 1, minor: 1 }, ..HttpRequest::default() };
+        let ctx = HttpLogContext { start: SystemTime::now(), duration: Duration::ZERO, bytes_sent: 0, request_number: 1, aborted: false };
+        logger.log(&req, &HttpResponse::new(), &ctx);
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join(format!("dhttp-filelog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("access.log");
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap_or_default();
+
+        // every line is over the limit, so each one ends up in its own file
+        let options = FileLogOptions { format: LogFormat::Common, max_size: Some(10), keep: 2, ..FileLogOptions::new() };
+        let logger = FileLogger::open(&path, &options).unwrap();
+        for route in ["/1", "/2", "/3"] {
+            log_request(&logger, route);
+        }
+        drop(logger);
+        assert_eq!(read(&path), "");
+        assert!(read(&numbered(&path, 1)).contains("GET /3 HTTP/1.1"));
+        assert!(read(&numbered(&path, 2)).contains("GET /2 HTTP/1.1"));
+        assert!(!numbered(&path, 3).exists());
+
+        // moved away by logrotate
+        let options = FileLogOptions { format: LogFormat::Common, ..FileLogOptions::new() };
+        let logger = FileLogger::open(&path, &options).unwrap();
+        log_request(&logger, "/old");
+        logger.sync();
+        std::fs::rename(&path, dir.join("moved.log")).unwrap();
+        logger.reopen();
+        logger.sync();
+        log_request(&logger, "/new");
+        drop(logger);
+        assert!(read(&dir.join("moved.log")).contains("/old"));
+        assert!(read(&path).contains("/new"));
+        assert!(!read(&path).contains("/old"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn disk_full() {
+        // every write fails with ENOSPC
+        let logger = FileLogger::open("/dev/full", &FileLogOptions::new()).unwrap();
+        for route in ["/1", "/2", "/3"] {
+            log_request(&logger, route);
+        }
+        logger.sync();
+        assert_eq!(logger.failed(), 3);
+        assert_eq!(logger.dropped(), 0);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn sighup() {
+        let path = std::env::temp_dir().join(format!("dhttp-sighup-{}.log", std::process::id()));
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let logger = FileLogger::open(&path, &FileLogOptions::new()).unwrap();
+            logger.reopen_on_sighup().unwrap();
+            let shared = Arc::downgrade(&logger.shared);
+            drop(logger);
+            // the task lets go of the shared state once it's aborted
+            tokio::task::yield_now().await;
+            assert!(shared.upgrade().is_none());
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn boundary() {
+        let day = Duration::from_secs(86400);
+        let now = UNIX_EPOCH + Duration::from_secs(86400 * 3 + 500);
+        assert_eq!(next_boundary(now, day), UNIX_EPOCH + Duration::from_secs(86400 * 4));
+    }
+}
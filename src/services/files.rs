@@ -36,7 +36,7 @@ impl HttpService for FilesService {
         if req.method != HttpMethod::Get && req.method != HttpMethod::Head {
             return Err(StatusCode::METHOD_NOT_ALLOWED.into());
         }
-        if req.len > 0 { return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into()); }
+        if req.len != Some(0) { return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into()); }
         Ok(())
     }
 }
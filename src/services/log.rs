@@ -1,7 +1,12 @@
-use chrono_lite::{Tm, time, localtime};
-use crate::core::{HttpLogger, HttpError};
+use std::fmt::Write;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono_lite::{Tm, time, time_t, gmtime, localtime};
+use crate::core::{HttpLogger, HttpLogContext, HttpError};
 use crate::reqres::{HttpRequest, HttpResponse};
 use crate::util::escape;
+use crate::util::httpdate::MONTHS;
 
 /// Default logger implementation
 pub struct DefaultLogger;
@@ -22,16 +27,171 @@ impl DefaultLogger {
         // User-Agents are long, print only first segment
         let agent = req.get_header("User-Agent").and_then(|a| a.split(' ').next()).unwrap_or("-");
         let agent = escape::control_sequences(agent);
-        format!("[{date}] {addr} {agent} {method} {route} -> {code} {desc}")
+        format!("[{date}] {addr} {agent} {method} {route} -> {code} {desc} [{}]", req.id)
     }
 }
 
 impl HttpLogger for DefaultLogger {
-    fn log(&self, req: &HttpRequest, res: &HttpResponse) {
+    fn log(&self, req: &HttpRequest, res: &HttpResponse, _ctx: &HttpLogContext) {
         println!("{}", self.format(req, res));
     }
 
-    fn err(&self, req: &HttpRequest, res: &HttpResponse, error: &dyn HttpError) {
+    fn err(&self, req: &HttpRequest, res: &HttpResponse, error: &dyn HttpError, _ctx: &HttpLogContext) {
         println!("{} ({}: {})", self.format(req, res), error.name(), error);
     }
+
+    fn accept_err(&self, error: &io::Error) {
+        println!("DrakoHTTP critical error: connection not accepted: {error}");
+    }
+}
+
+/// Standard access log formats
+///
+/// Times are in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Common Log Format: `192.0.2.1 - - [17/Oct/2026:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`
+    Common,
+    /// Combined Log Format: Common with `"Referer" "User-Agent"` at the end
+    Combined,
+    /// JSON object per line, with everything from [`HttpLogContext`] and the error
+    Json,
+}
+
+/// `2026-10-17T13:55:36.250Z`
+fn iso_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let Tm { tm_mday, tm_mon, tm_year, tm_hour, tm_min, tm_sec, .. } = gmtime(secs.as_secs() as time_t).expect("date out of range");
+    let (year, month, millis) = (tm_year + 1900, tm_mon + 1, secs.subsec_millis());
+    format!("{year}-{month:02}-{tm_mday:02}T{tm_hour:02}:{tm_min:02}:{tm_sec:02}.{millis:03}Z")
+}
+
+/// `17/Oct/2026:13:55:36 +0000`
+fn clf_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let Tm { tm_mday, tm_mon, tm_year, tm_hour, tm_min, tm_sec, .. } = gmtime(secs.as_secs() as time_t).expect("date out of range");
+    let (year, month) = (tm_year + 1900, MONTHS[tm_mon as usize]);
+    format!("{tm_mday:02}/{month}/{year}:{tm_hour:02}:{tm_min:02}:{tm_sec:02} +0000")
+}
+
+impl LogFormat {
+    /// Formats a line, without the line break. Errors are only shown in JSON
+    pub fn format(&self, req: &HttpRequest, res: &HttpResponse, ctx: &HttpLogContext, error: Option<&dyn HttpError>) -> String {
+        if *self == LogFormat::Json {
+            let mut line = String::new();
+            write!(line, r#"{{"time":"{}""#, iso_time(ctx.start)).unwrap();
+            write!(line, r#","id":"{}","addr":"{}","port":{}"#, escape::json(&req.id), req.addr, req.port).unwrap();
+            write!(line, r#","method":"{}","route":"{}""#, escape::json(req.method.as_str()), escape::json(&req.route)).unwrap();
+            write!(line, r#","version":"{:?}","scheme":"{}""#, req.version, escape::json(&req.scheme)).unwrap();
+            let optional = |value: Option<&str>| value.map_or("null".to_string(), |v| format!("\"{}\"", escape::json(v)));
+            write!(line, r#","host":{}"#, optional(req.host.as_deref())).unwrap();
+            write!(line, r#","status":{},"bytes":{}"#, res.code, ctx.bytes_sent).unwrap();
+            write!(line, r#","duration_ms":{:.3}"#, ctx.duration.as_secs_f64() * 1000.0).unwrap();
+            write!(line, r#","request_number":{},"aborted":{}"#, ctx.request_number, ctx.aborted).unwrap();
+            write!(line, r#","referer":{},"user_agent":{}"#, optional(req.get_header("Referer")), optional(req.get_header("User-Agent"))).unwrap();
+            if let Some(error) = error {
+                write!(line, r#","error":{{"name":"{}","message":"{}"}}"#, escape::json(error.name()), escape::json(&error.to_string())).unwrap();
+            }
+            line.push('}');
+            return line;
+        }
+
+        let method = escape::log_quoted(req.method.as_str());
+        // the request line as received, requests built by hand may only have a route
+        let target = if req.raw_target.is_empty() { &req.route } else { &req.raw_target };
+        let target = escape::log_quoted(target);
+        // `-` means no body
+        let bytes = if ctx.bytes_sent == 0 { "-".to_string() } else { ctx.bytes_sent.to_string() };
+        let mut line = format!(
+            "{} - - [{}] \"{method} {target} {:?}\" {} {bytes}",
+            req.addr, clf_time(ctx.start), req.version, res.code,
+        );
+        if *self == LogFormat::Combined {
+            let quoted = |name| escape::log_quoted(req.get_header(name).unwrap_or("-"));
+            write!(line, " \"{}\" \"{}\"", quoted("Referer"), quoted("User-Agent")).unwrap();
+        }
+        line
+    }
+
+    /// Formats a connection that was not accepted, see [`HttpLogger::accept_err`]
+    pub fn format_accept_err(&self, time: SystemTime, error: &io::Error) -> String {
+        let message = error.to_string();
+        match self {
+            LogFormat::Json => format!(r#"{{"time":"{}","error":{{"name":"accept","message":"{}"}}}}"#, iso_time(time), escape::json(&message)),
+            LogFormat::Common | LogFormat::Combined => format!("[{}] connection not accepted: {}", clf_time(time), escape::control_sequences(&message)),
+        }
+    }
+}
+
+/// Prints access log lines in a standard format to stdout
+pub struct AccessLogger {
+    pub format: LogFormat,
+}
+
+impl AccessLogger {
+    pub fn new(format: LogFormat) -> AccessLogger {
+        AccessLogger { format }
+    }
+}
+
+impl HttpLogger for AccessLogger {
+    fn log(&self, req: &HttpRequest, res: &HttpResponse, ctx: &HttpLogContext) {
+        println!("{}", self.format.format(req, res, ctx, None));
+    }
+
+    fn err(&self, req: &HttpRequest, res: &HttpResponse, error: &dyn HttpError, ctx: &HttpLogContext) {
+        println!("{}", self.format.format(req, res, ctx, Some(error)));
+    }
+
+    fn accept_err(&self, error: &io::Error) {
+        println!("{}", self.format.format_accept_err(SystemTime::now(), error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::reqres::{HttpHeader, HttpVersion, RequestTarget, StatusCode};
+
+    #[test]
+    fn formats() {
+        let req = HttpRequest {
+            route: "/a\"b".to_string(),
+            version: HttpVersion { major: 1, minor: 1 },
+            headers: vec![HttpHeader { name: "User-Agent".to_string(), value: "curl/8.0".to_string() }],
+            addr: "192.0.2.1".parse().unwrap(),
+            port: 51000,
+            host: Some("example.com".to_string()),
+            id: "abc".to_string(),
+            ..HttpRequest::default()
+        };
+        let mut res = HttpResponse::new();
+        res.code = StatusCode::NOT_FOUND;
+        let ctx = HttpLogContext {
+            start: SystemTime::UNIX_EPOCH + Duration::from_millis(1740607859250),
+            duration: Duration::from_micros(1500),
+            bytes_sent: 0,
+            request_number: 2,
+            aborted: false,
+        };
+        let error = std::io::Error::other("no \"such\" file");
+
+        assert_eq!(LogFormat::Common.format(&req, &res, &ctx, None), r#"192.0.2.1 - - [26/Feb/2025:22:10:59 +0000] "GET /a\"b HTTP/1.1" 404 -"#);
+        assert_eq!(LogFormat::Combined.format(&req, &res, &ctx, Some(&error)), r#"192.0.2.1 - - [26/Feb/2025:22:10:59 +0000] "GET /a\"b HTTP/1.1" 404 - "-" "curl/8.0""#);
+        assert_eq!(LogFormat::Json.format(&req, &res, &ctx, Some(&error)), concat!(
+            r#"{"time":"2025-02-26T22:10:59.250Z","id":"abc","addr":"192.0.2.1","port":51000,"method":"GET","route":"/a\"b","version":"HTTP/1.1","#,
+            r#""scheme":"http","host":"example.com","status":404,"bytes":0,"duration_ms":1.500,"request_number":2,"aborted":false,"#,
+            r#""referer":null,"user_agent":"curl/8.0","error":{"name":"io::Error","message":"no \"such\" file"}}"#,
+        ));
+
+        // absolute-form is logged as sent, with the query
+        let req = HttpRequest { route: "/p?q".to_string(), target: RequestTarget::Absolute, raw_target: "http://host/p?q".to_string(), ..req };
+        assert_eq!(LogFormat::Common.format(&req, &res, &ctx, None), r#"192.0.2.1 - - [26/Feb/2025:22:10:59 +0000] "GET http://host/p?q HTTP/1.1" 404 -"#);
+
+        let error = std::io::Error::other("too many open files");
+        assert_eq!(LogFormat::Common.format_accept_err(ctx.start, &error), "[26/Feb/2025:22:10:59 +0000] connection not accepted: too many open files");
+        assert_eq!(LogFormat::Json.format_accept_err(ctx.start, &error), r#"{"time":"2025-02-26T22:10:59.250Z","error":{"name":"accept","message":"too many open files"}}"#);
+    }
 }
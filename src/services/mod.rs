@@ -6,9 +6,15 @@ mod router;
 pub use router::Router;
 mod files;
 pub use files::FilesService;
+#[cfg(feature = "compression")]
+pub mod compression;
+#[cfg(feature = "compression")]
+pub use compression::Compression;
 
 mod log;
-pub use log::DefaultLogger;
+pub use log::{DefaultLogger, AccessLogger, LogFormat};
+mod filelog;
+pub use filelog::{FileLogger, FileLogOptions};
 
 mod errorpage;
 pub use errorpage::ErrorPageHandler;
@@ -31,6 +31,13 @@ use crate::reqres::{HttpRequest, StatusCode};
 /// so `/files/something` becomes `/something` in the `route` argument. Original route is still
 /// accessible via `req.route`
 ///
+/// `*` of `OPTIONS *` is only matched by an exact `*` route:
+/// ```
+/// # use dhttp::services::{DefaultService, Router};
+/// # let mut router = Router::new();
+/// router.add("*", DefaultService);
+/// ```
+///
 /// Nested routes are implemented with a linear search, consider something more optimized
 /// if you have thousands of them
 ///
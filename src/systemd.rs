@@ -0,0 +1,204 @@
+//! systemd socket activation and readiness notifications
+//!
+//! See `sd_listen_fds(3)` and `sd_notify(3)`, libsystemd is not needed for either
+
+use std::ffi::OsStr;
+use std::io::{self, ErrorKind};
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use socket2::{Socket as RawSocket, Type};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::server::{HttpServer, HttpListener, ListenerGroup, Socket};
+
+/// First passed file descriptor, after stdin, stdout and stderr
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Passed sockets can only be taken once
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Finds passed descriptors and their names in `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`
+fn parse_env(pid: Option<&str>, fds: Option<&str>, names: Option<&str>, our_pid: u32) -> io::Result<Vec<(RawFd, String)>> {
+    let invalid = |what| io::Error::new(ErrorKind::InvalidInput, format!("invalid {what}"));
+    // sockets were passed to some other process
+    let (Some(pid), Some(fds)) = (pid, fds) else { return Ok(vec![]) };
+    if pid.parse::<u32>().map_err(|_| invalid("LISTEN_PID"))? != our_pid { return Ok(vec![]); }
+
+    let count: RawFd = fds.parse().map_err(|_| invalid("LISTEN_FDS"))?;
+    if !(0..=RawFd::MAX - LISTEN_FDS_START).contains(&count) { return Err(invalid("LISTEN_FDS")); }
+    let mut names: Vec<String> = names.map(|n| n.split(':').map(String::from).collect()).unwrap_or_default();
+    if names.len() != count as usize {
+        // names are optional, and useless if they don't match
+        names = vec!["unknown".to_string(); count as usize];
+    }
+    Ok((LISTEN_FDS_START..).zip(names).collect())
+}
+
+/// Wraps a passed listening socket, TCP or Unix
+///
+/// # Safety
+/// `fd` must be open, and nothing else can own it
+unsafe fn listener_from_fd(fd: RawFd) -> io::Result<HttpListener> {
+    // SAFETY: guaranteed by the caller
+    let sock = unsafe { RawSocket::from_raw_fd(fd) };
+    // not for the processes we start
+    sock.set_cloexec(true)?;
+    sock.set_nonblocking(true)?;
+    if sock.r#type()? != Type::STREAM {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("socket {fd} is not a stream socket")));
+    }
+    let unix = sock.local_addr()?.is_unix();
+    let fd = OwnedFd::from(sock);
+    let socket = if unix {
+        // socket file belongs to systemd, it's not removed
+        Socket::Unix(UnixListener::from_std(fd.into())?, None)
+    } else {
+        Socket::Tcp(TcpListener::from_std(fd.into())?)
+    };
+    Ok(HttpListener::new(socket))
+}
+
+/// Takes the sockets passed by systemd, with their names from `FileDescriptorName=`
+///
+/// Returns nothing if the process was not socket-activated, or if the sockets were already taken.
+/// The variables are unset once the sockets are taken, so child processes don't see them.
+/// Must be called inside the tokio runtime
+pub fn listeners() -> io::Result<Vec<(String, HttpListener)>> {
+    let var = |name| std::env::var(name).ok();
+    let fds = parse_env(var("LISTEN_PID").as_deref(), var("LISTEN_FDS").as_deref(), var("LISTEN_FDNAMES").as_deref(), std::process::id())?;
+    if fds.is_empty() || TAKEN.swap(true, Ordering::SeqCst) { return Ok(vec![]); }
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        // SAFETY: like `sd_listen_fds(1)`, this is expected at startup, before other threads look at the environment
+        unsafe { std::env::remove_var(name) };
+    }
+    // SAFETY: systemd has passed these descriptors to us, and they are taken only once
+    fds.into_iter().map(|(fd, name)| Ok((name, unsafe { listener_from_fd(fd)? }))).collect()
+}
+
+/// Sends a state to systemd, like `READY=1`
+///
+/// Does nothing if `NOTIFY_SOCKET` is not set (service is not `Type=notify`)
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else { return Ok(()) };
+    notify_to(&path, state)
+}
+
+fn notify_to(path: &OsStr, state: &str) -> io::Result<()> {
+    let sock = UnixDatagram::unbound()?;
+    let bytes = path.as_encoded_bytes();
+    if let Some(name) = bytes.strip_prefix(b"@") {
+        // abstract socket
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sock.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            return Err(ErrorKind::Unsupported.into());
+        }
+    }
+    sock.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+/// Serves every socket passed by systemd with one server
+///
+/// Sends `READY=1` when sockets are taken, and `STOPPING=1` on shutdown. `SIGTERM` from systemd triggers it too
+pub async fn serve_systemd(server: impl Into<Arc<HttpServer>>) -> io::Result<()> {
+    let server = server.into();
+    let listeners = listeners()?;
+    if listeners.is_empty() {
+        return Err(io::Error::new(ErrorKind::NotFound, "no sockets were passed by systemd"));
+    }
+    let mut group = ListenerGroup::new();
+    for (_name, listener) in listeners {
+        group.add(listener, Arc::clone(&server));
+    }
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    server.shutdown.trigger_on(async move { sigterm.recv().await; });
+    notify("READY=1")?;
+
+    // sent when the drain starts
+    let shutdown = server.shutdown.clone();
+    let stopping = tokio::spawn(async move {
+        shutdown.wait().await;
+        let _ = notify("STOPPING=1");
+    });
+    let result = group.serve().await;
+    // errors stop it without a shutdown, it's sent then too
+    server.shutdown.trigger();
+    let _ = stopping.await;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::IntoRawFd;
+
+    use super::*;
+
+    #[test]
+    fn env() {
+        let fds = parse_env(Some("42"), Some("2"), Some("http:admin"), 42).unwrap();
+        assert_eq!(fds, [(3, "http".to_string()), (4, "admin".to_string())]);
+        let fds = parse_env(Some("42"), Some("2"), Some("http"), 42).unwrap();
+        assert_eq!(fds, [(3, "unknown".to_string()), (4, "unknown".to_string())]);
+        // for some other process
+        assert!(parse_env(Some("43"), Some("2"), None, 42).unwrap().is_empty());
+        assert!(parse_env(None, None, None, 42).unwrap().is_empty());
+        assert!(parse_env(Some("42"), Some("-1"), None, 42).is_err());
+        assert!(parse_env(Some("x"), Some("1"), None, 42).is_err());
+    }
+
+    #[test]
+    fn passed_sockets() {
+        tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap().block_on(async {
+            let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = tcp.local_addr().unwrap();
+            let listener = unsafe { listener_from_fd(tcp.into_raw_fd()) }.unwrap();
+            assert_eq!(listener.local_addr().unwrap(), addr);
+
+            let path = std::env::temp_dir().join(format!("dhttp-systemd-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+            let listener = unsafe { listener_from_fd(unix.into_raw_fd()) }.unwrap();
+            assert!(matches!(listener.socket, Socket::Unix(_, None)));
+            std::fs::remove_file(&path).unwrap();
+
+            let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            assert!(unsafe { listener_from_fd(udp.into_raw_fd()) }.is_err());
+        });
+    }
+
+    #[test]
+    fn notify_socket() {
+        let path = std::env::temp_dir().join(format!("dhttp-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        notify_to(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 16];
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            let name = format!("dhttp-notify-{}", std::process::id());
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+            let systemd = UnixDatagram::bind_addr(&addr).unwrap();
+            notify_to(OsStr::new(&format!("@{name}")), "STOPPING=1").unwrap();
+            let len = systemd.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"STOPPING=1");
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Unix domain socket listener
+
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::BufReader;
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::core::connection::HttpConnection;
+use crate::server::{HttpServer, HttpListener, Listener, Socket};
+
+/// Options of [`HttpListener::bind_unix`] and [`serve_unix_with`]
+pub struct UnixOptions {
+    /// Permissions of the socket file, like `0o660`. Process umask decides if `None`
+    ///
+    /// The socket is bound in a private directory next to the path, and moved in place once it has this mode
+    pub mode: Option<u32>,
+    /// Remove the socket file left by a previous run, if nobody listens on it anymore
+    pub remove_stale: bool,
+    /// Remove the socket file when server stops
+    pub remove_on_exit: bool,
+}
+
+impl UnixOptions {
+    pub fn new() -> UnixOptions {
+        UnixOptions { mode: None, remove_stale: true, remove_on_exit: true }
+    }
+}
+
+impl Default for UnixOptions {
+    fn default() -> UnixOptions {
+        UnixOptions::new()
+    }
+}
+
+/// Unix sockets have no IP address, peer is reported as `127.0.0.1` since it's on the same machine
+impl HttpConnection for BufReader<UnixStream> {
+    fn getpeername(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+    }
+
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
+
+impl Listener for UnixListener {
+    type Conn = UnixStream;
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<UnixStream>> {
+        UnixListener::poll_accept(self, cx).map_ok(|(conn, _addr)| conn)
+    }
+}
+
+/// Removes the socket file if connecting to it is refused
+///
+/// Anything that is not a socket is left alone, and binding fails on it
+fn remove_stale(path: &Path) -> io::Result<()> {
+    let Ok(meta) = std::fs::symlink_metadata(path) else { return Ok(()) };
+    if !meta.file_type().is_socket() { return Ok(()); }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(ErrorKind::AddrInUse, "another server is listening on this socket")),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => std::fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
+
+/// Binds a socket that nobody can connect to before it has `mode`
+///
+/// A new socket gets its mode from the umask, so it's created in a directory only we can access,
+/// then moved to `path`
+fn bind_with_mode(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    let mut dir = path.as_os_str().to_owned();
+    dir.push(format!(".{}.tmp", std::process::id()));
+    let dir = PathBuf::from(dir);
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let tmp = dir.join("s");
+    let result = (|| {
+        let listener = UnixListener::bind(&tmp)?;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(mode))?;
+        // rename would replace anything, while bind fails on an existing file
+        if std::fs::symlink_metadata(path).is_ok() { return Err(ErrorKind::AddrInUse.into()); }
+        std::fs::rename(&tmp, path)?;
+        Ok(listener)
+    })();
+    let _ = std::fs::remove_file(&tmp);
+    let _ = std::fs::remove_dir(&dir);
+    result
+}
+
+impl HttpListener {
+    /// Creates a listening Unix socket
+    ///
+    /// Must be called inside the tokio runtime
+    pub fn bind_unix(path: impl AsRef<Path>, options: &UnixOptions) -> io::Result<HttpListener> {
+        let path = path.as_ref();
+        if options.remove_stale {
+            remove_stale(path)?;
+        }
+        let listener = match options.mode {
+            Some(mode) => bind_with_mode(path, mode)?,
+            None => UnixListener::bind(path)?,
+        };
+        let remove = options.remove_on_exit.then(|| path.to_path_buf());
+        Ok(HttpListener::new(Socket::Unix(listener, remove)))
+    }
+}
+
+/// Starts handling connections on a given [`HttpServer`] on a Unix socket, with default [`UnixOptions`]
+pub async fn serve_unix(path: impl AsRef<Path>, server: impl Into<Arc<HttpServer>>) -> io::Result<()> {
+    serve_unix_with(path, server, &UnixOptions::new()).await
+}
+
+/// Starts handling connections on a given [`HttpServer`] on a Unix socket
+pub async fn serve_unix_with(path: impl AsRef<Path>, server: impl Into<Arc<HttpServer>>, options: &UnixOptions) -> io::Result<()> {
+    HttpListener::bind_unix(path, options)?.serve(server).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_socket() {
+        let path = std::env::temp_dir().join(format!("dhttp-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let live = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert_eq!(remove_stale(&path).unwrap_err().kind(), ErrorKind::AddrInUse);
+        // socket file stays after the listener is closed
+        drop(live);
+        assert!(path.exists());
+        remove_stale(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn mode() {
+        let path = std::env::temp_dir().join(format!("dhttp-test-mode-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        crate::tokio_rt().unwrap().block_on(async {
+            let listener = bind_with_mode(&path, 0o600).unwrap();
+            let meta = std::fs::symlink_metadata(&path).unwrap();
+            assert!(meta.file_type().is_socket());
+            assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+            // socket still works after the move, and the directory is gone
+            UnixStream::connect(&path).await.unwrap();
+            let mut dir = path.clone().into_os_string();
+            dir.push(format!(".{}.tmp", std::process::id()));
+            assert!(!Path::new(&dir).exists());
+            // existing files are not replaced
+            assert_eq!(bind_with_mode(&path, 0o600).unwrap_err().kind(), ErrorKind::AddrInUse);
+            drop(listener);
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
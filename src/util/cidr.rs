@@ -0,0 +1,117 @@
+//! IP address ranges
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// IP network in CIDR notation, like `10.0.0.0/8` or `fd00::/8`
+///
+/// Address without a prefix means only that address.
+/// IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) match IPv4 networks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Returns `None` if the prefix is too long for the address
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Cidr> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max { return None; }
+        // `::ffff:10.0.0.0/104` is `10.0.0.0/8`
+        if let IpAddr::V6(v6) = addr && let Some(v4) = v6.to_ipv4_mapped() && prefix >= 96 {
+            return Some(Cidr { addr: IpAddr::V4(v4), prefix: prefix - 96 });
+        }
+        Some(Cidr { addr, prefix })
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Error of parsing a [`Cidr`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCidr;
+
+impl fmt::Display for InvalidCidr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("invalid CIDR")
+    }
+}
+
+impl std::error::Error for InvalidCidr {}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Cidr, InvalidCidr> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| InvalidCidr)?;
+        let prefix = match prefix {
+            // leading `+` and zeros are not allowed
+            Some(p) if p.starts_with(|c: char| c.is_ascii_digit()) && (p == "0" || !p.starts_with('0')) => p.parse().map_err(|_| InvalidCidr)?,
+            Some(_) => return Err(InvalidCidr),
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Cidr::new(addr, prefix).ok_or(InvalidCidr)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn contains() {
+        assert!(cidr("10.0.0.0/8").contains(ip("10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+        assert!(cidr("0.0.0.0/0").contains(ip("1.2.3.4")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+        assert!(cidr("192.168.1.1").contains(ip("192.168.1.1")));
+        assert!(!cidr("192.168.1.1").contains(ip("192.168.1.2")));
+        assert!(cidr("fd00::/8").contains(ip("fd12::1")));
+        assert!(!cidr("fd00::/8").contains(ip("fe80::1")));
+        assert!(cidr("::/0").contains(ip("::1")));
+    }
+
+    #[test]
+    fn parse() {
+        for s in ["10.0.0.0/33", "::/129", "10.0.0.0/", "10.0.0.0/+8", "10.0.0.0/08", "10.0.0/8", "example.com/8"] {
+            assert_eq!(s.parse::<Cidr>(), Err(InvalidCidr), "{s}");
+        }
+        assert_eq!(cidr("10.0.0.0/8").to_string(), "10.0.0.0/8");
+        assert_eq!(cidr("::1").to_string(), "::1/128");
+        assert_eq!(cidr("::ffff:10.0.0.0/104").to_string(), "10.0.0.0/8");
+    }
+}
@@ -31,3 +31,32 @@ pub(crate) fn control_sequences(s: &str) -> String {
     }
     out
 }
+
+/// Escapes a value for a quoted field of an access log, like Apache does
+pub(crate) fn log_quoted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '"' | '\\' => { out.push('\\'); out.push(ch); }
+            _ if ch < ' ' || ch == '\x7f' => write!(&mut out, "\\x{:02x}", ch as u8).unwrap(),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Escapes a JSON string, without the quotes
+pub(crate) fn json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '"' | '\\' => { out.push('\\'); out.push(ch); }
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            _ if ch < ' ' || ch == '\x7f' => write!(&mut out, "\\u{:04x}", ch as u32).unwrap(),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
@@ -16,7 +16,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use chrono_lite::{time_t, Tm, gmtime, time};
 
 const WEEKDAYS: &[&str] = &["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
-const MONTHS: &[&str] = &["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+pub(crate) const MONTHS: &[&str] = &["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
 
 fn httpdate(tm: Tm) -> String {
     let Tm { tm_wday, tm_mday, tm_mon, tm_year, tm_hour, tm_min, tm_sec, .. } = tm;
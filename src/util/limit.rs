@@ -0,0 +1,70 @@
+//! Counters of running connections and requests
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::sync::Notify;
+
+/// Number of running things, limit is checked when one is added
+#[derive(Default)]
+pub(crate) struct Limit {
+    running: AtomicUsize,
+    released: Notify,
+}
+
+/// Running thing, it's counted until dropped
+pub(crate) struct Permit(Arc<Limit>);
+
+impl Limit {
+    /// Returns `None` if `max` is reached
+    pub(crate) fn try_acquire(self: &Arc<Self>, max: Option<usize>) -> Option<Permit> {
+        let max = max.unwrap_or(usize::MAX);
+        self.running.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < max).then_some(n + 1)).ok()?;
+        Some(Permit(Arc::clone(self)))
+    }
+
+    /// Waits until something is released if `max` is reached
+    pub(crate) async fn acquire(self: &Arc<Self>, max: Option<usize>) -> Permit {
+        loop {
+            // created before the check, so a release in between is not missed
+            let released = self.released.notified();
+            if let Some(permit) = self.try_acquire(max) { return permit; }
+            released.await;
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::AcqRel);
+        self.0.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit() {
+        let limit = Arc::new(Limit::default());
+        let first = limit.try_acquire(Some(2)).unwrap();
+        let _second = limit.try_acquire(Some(2)).unwrap();
+        assert!(limit.try_acquire(Some(2)).is_none());
+        assert!(limit.try_acquire(None).is_some());
+        assert_eq!(limit.running.load(Ordering::Acquire), 2);
+
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        rt.block_on(async {
+            let waiting = tokio::spawn({
+                let limit = Arc::clone(&limit);
+                async move { limit.acquire(Some(2)).await }
+            });
+            tokio::task::yield_now().await;
+            assert!(!waiting.is_finished());
+            drop(first);
+            let _third = waiting.await.unwrap();
+            assert_eq!(limit.running.load(Ordering::Acquire), 2);
+        });
+    }
+}
@@ -1,6 +1,11 @@
 //! Utility functions
 
+pub mod cidr;
 pub mod httpdate;
 pub mod path;
 pub(crate) mod escape;
 pub(crate) mod future;
+pub(crate) mod limit;
+pub(crate) mod request_id;
+#[cfg(target_os = "linux")]
+pub(crate) mod sendfile;
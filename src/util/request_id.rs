@@ -0,0 +1,44 @@
+//! Request IDs
+
+use std::hash::{BuildHasher, RandomState};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Longest ID that is accepted from `X-Request-Id`
+const MAX_LEN: usize = 128;
+
+/// Random for every process, so IDs of different instances don't collide
+static PREFIX: LazyLock<u64> = LazyLock::new(|| RandomState::new().hash_one(std::process::id()));
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Generates a new unique ID, 32 hex digits
+pub(crate) fn generate() -> String {
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:016x}{n:016x}", *PREFIX)
+}
+
+/// IDs from clients can end up in logs and HTML, so only a safe subset is accepted:
+/// letters, digits and `-_.:+/=@`
+pub(crate) fn is_valid(id: &str) -> bool {
+    (1..=MAX_LEN).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:+/=@".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids() {
+        let (first, second) = (generate(), generate());
+        assert_ne!(first, second);
+        assert_eq!(first.len(), 32);
+        assert!(is_valid(&first));
+
+        assert!(is_valid("f47ac10b-58cc-4372-a567-0e02b2c3d479"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("a b"));
+        assert!(!is_valid("<script>"));
+        assert!(!is_valid("a\r\nb"));
+        assert!(!is_valid(&"a".repeat(MAX_LEN + 1)));
+    }
+}
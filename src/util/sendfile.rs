@@ -0,0 +1,82 @@
+//! Zero-copy file sending with `sendfile(2)`
+
+use std::io::{self, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, Interest};
+use tokio::net::TcpStream;
+
+/// Linux sends at most this many bytes per call
+const MAX_SENDFILE: u64 = 0x7ffff000;
+
+/// Sends `len` bytes of a file, starting from its current position
+///
+/// Fails with [`ErrorKind::TimedOut`] if socket does not become writable within `timeout`
+pub(crate) async fn sendfile(tcp: &TcpStream, file: &mut File, len: u64, timeout: Option<Duration>) -> io::Result<()> {
+    let mut offset = file.stream_position().await? as libc::off_t;
+    let mut left = len;
+    while left > 0 {
+        let count = left.min(MAX_SENDFILE) as usize;
+        let send = tcp.async_io(Interest::WRITABLE, || {
+            // SAFETY: both file descriptors are valid for the duration of this call
+            let sent = unsafe { libc::sendfile(tcp.as_raw_fd(), file.as_raw_fd(), &mut offset, count) };
+            if sent < 0 { Err(io::Error::last_os_error()) } else { Ok(sent as u64) }
+        });
+        let sent = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, send).await.map_err(|_| io::Error::from(ErrorKind::TimedOut))??,
+            None => send.await?,
+        };
+        // File was truncated while sending
+        if sent == 0 { return Err(ErrorKind::UnexpectedEof.into()); }
+        left -= sent;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::SeekFrom;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn send_range() {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            let path = std::env::temp_dir().join(format!("dhttp-sendfile-{}", std::process::id()));
+            let data: Vec<u8> = (0..100000u32).map(|i| i as u8).collect();
+            std::fs::write(&path, &data).unwrap();
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+            let (server, _) = listener.accept().await.unwrap();
+
+            let mut file = File::open(&path).await.unwrap();
+            file.seek(SeekFrom::Start(1000)).await.unwrap();
+            let read = tokio::spawn(async move {
+                let mut out = vec![];
+                let mut client = client;
+                client.read_to_end(&mut out).await.unwrap();
+                out
+            });
+            sendfile(&server, &mut file, 90000, None).await.unwrap();
+            drop(server);
+
+            assert_eq!(read.await.unwrap(), &data[1000..91000]);
+            // past the end of file
+            file.seek(SeekFrom::Start(99990)).await.unwrap();
+            let listener2 = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let _client = TcpStream::connect(listener2.local_addr().unwrap()).await.unwrap();
+            let (server, _) = listener2.accept().await.unwrap();
+            let err = sendfile(&server, &mut file, 20, None).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+            std::fs::remove_file(&path).unwrap();
+        });
+    }
+}